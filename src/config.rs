@@ -0,0 +1,913 @@
+use std::{
+    fs,
+    io,
+    fmt,
+    mem,
+    time::Duration,
+    net::{SocketAddr, ToSocketAddrs},
+    path::{Path, PathBuf}
+};
+
+use serde::Deserialize;
+
+use crate::server::{
+    CacheRule,
+    RedirectRule,
+    ClientAuthRule,
+    ClientAuthPolicy,
+    CompressionSettings,
+    ForwardTarget
+};
+
+
+pub const DEFAULT_PATH: &str = "funserver.toml";
+
+pub const USAGE: &str = "usage: funserver [options] [address] [document root]
+
+options:
+    -c, --config <path>        config file to load (default funserver.toml)
+    -l, --listen <address>     address to listen on with tls, can be given multiple times
+        --http <address>       address to listen on without tls, can be given multiple times
+    -r, --root <path>          document root
+        --cert <path>          certificate chain file
+        --key <path>           private key file (default is to look in the cert file)
+        --client-ca <path>     ca bundle to verify client certificates with
+        --idle-timeout <secs>  close connections that stay quiet for this long
+        --log-requests         print every parsed request
+    -q, --quiet                dont print connections opening and closing
+        --generate-cert        write a self signed certificate to the cert (and key) path
+        --cert-name <name>     name or ip to generate the certificate for, can be repeated
+                               (defaults to localhost, the host names and the listener ips)
+    -h, --help                 print this";
+
+pub enum Error
+{
+    Read(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Invalid(String),
+    Usage(String)
+}
+
+impl fmt::Display for Error
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match self
+        {
+            Error::Read(path, err) =>
+            {
+                write!(f, "error reading config at {} ({err})", path.display())
+            },
+            Error::Parse(path, err) =>
+            {
+                write!(f, "error parsing config at {} ({err})", path.display())
+            },
+            Error::Invalid(reason) => write!(f, "invalid config ({reason})"),
+            Error::Usage(reason) => write!(f, "{reason}\n\n{USAGE}")
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ListenerMode
+{
+    #[default]
+    Tls,
+    // serves the same stuff without any encryption
+    Plain,
+    // only tells the client to go to the https version
+    Redirect
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ListenerConfig
+{
+    // only shows up in the logs
+    pub name: Option<String>,
+    pub address: String,
+    #[serde(default)]
+    pub mode: ListenerMode,
+    // where redirects point to, defaults to the port of the first tls listener
+    pub https_port: Option<u16>,
+    // defaults to only if theres an ipv4 listener on the same port
+    pub ipv6_only: Option<bool>
+}
+
+impl ListenerConfig
+{
+    fn new(address: String, mode: ListenerMode) -> Self
+    {
+        Self{name: None, address, mode, https_port: None, ipv6_only: None}
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig
+{
+    pub cert: PathBuf,
+    pub key: Option<PathBuf>,
+    // der encoded ocsp response for the cert, stapled to handshakes
+    pub ocsp: Option<PathBuf>,
+    // ca bundle that client certificates get verified against
+    pub client_ca: Option<PathBuf>,
+    // seconds between checking if the certificate files changed, 0 turns it off
+    pub reload_interval: u64,
+    pub versions: Vec<TlsVersion>,
+    // rustls names in order of preference, empty means all of them
+    pub cipher_suites: Vec<String>,
+    // stateless resumption with encrypted tickets
+    pub session_tickets: bool,
+    // how many sessions get remembered for resuming, 0 turns it off
+    pub session_cache: usize
+}
+
+impl Default for TlsConfig
+{
+    fn default() -> Self
+    {
+        Self{
+            cert: PathBuf::from("cert.pem"),
+            key: None,
+            ocsp: None,
+            client_ca: None,
+            reload_interval: 10,
+            versions: vec![TlsVersion::Tls12, TlsVersion::Tls13],
+            cipher_suites: Vec::new(),
+            session_tickets: true,
+            session_cache: 256
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum TlsVersion
+{
+    #[serde(rename = "1.2")]
+    Tls12,
+    #[serde(rename = "1.3")]
+    Tls13
+}
+
+// all in seconds
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimeoutsConfig
+{
+    pub idle: u64,
+    pub write: u64
+}
+
+impl Default for TimeoutsConfig
+{
+    fn default() -> Self
+    {
+        Self{idle: 5, write: 30}
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig
+{
+    pub connections: bool,
+    pub requests: bool
+}
+
+impl Default for LogConfig
+{
+    fn default() -> Self
+    {
+        Self{connections: true, requests: false}
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PostConfig
+{
+    pub forward: Vec<ForwardTarget>
+}
+
+// a site picked by the sni name and host header, anything missing is the same as the default site
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HostConfig
+{
+    pub names: Vec<String>,
+    pub root: Option<PathBuf>,
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+    pub ocsp: Option<PathBuf>,
+    pub post: Option<PostConfig>,
+    pub directory_listing: Option<bool>
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config
+{
+    // shorthand for tls listeners
+    pub listen: Vec<String>,
+    pub listener: Vec<ListenerConfig>,
+    pub root: PathBuf,
+    pub tls: TlsConfig,
+    pub timeouts: TimeoutsConfig,
+    pub log: LogConfig,
+    pub post: PostConfig,
+    pub cache_control: Vec<CacheRule>,
+    pub redirect: Vec<RedirectRule>,
+    pub client_auth: Vec<ClientAuthRule>,
+    pub compression: CompressionSettings,
+    pub directory_listing: bool,
+    pub host: Vec<HostConfig>
+}
+
+impl Default for Config
+{
+    fn default() -> Self
+    {
+        Self{
+            listen: Vec::new(),
+            listener: Vec::new(),
+            root: PathBuf::from("."),
+            tls: TlsConfig::default(),
+            timeouts: TimeoutsConfig::default(),
+            log: LogConfig::default(),
+            post: PostConfig::default(),
+            cache_control: Vec::new(),
+            redirect: Vec::new(),
+            client_auth: Vec::new(),
+            compression: CompressionSettings::default(),
+            directory_listing: false,
+            host: Vec::new()
+        }
+    }
+}
+
+pub enum Command
+{
+    Help,
+    Serve(Config),
+    // writes a self signed certificate for these names to where the config expects it
+    GenerateCert(Config, Vec<String>)
+}
+
+impl Command
+{
+    // loads the config file and applies the command line on top of it
+    pub fn from_args(args: impl IntoIterator<Item=String>) -> Result<Self, Error>
+    {
+        let Some(mut args) = Args::parse(args)? else { return Ok(Self::Help) };
+
+        // the default config file is allowed to not exist but an explicitly given one isnt
+        let mut config = match &args.config
+        {
+            Some(path) => Config::load(path)?,
+            None => Config::load_optional(DEFAULT_PATH)?
+        };
+
+        let generate_cert = args.generate_cert;
+        let cert_names = mem::take(&mut args.cert_names);
+
+        config.apply(args);
+
+        // theres no certificate to check yet if its getting generated
+        config.validate(!generate_cert)?;
+
+        if generate_cert
+        {
+            let names = if cert_names.is_empty() { config.local_names() } else { cert_names };
+
+            Ok(Self::GenerateCert(config, names))
+        } else
+        {
+            Ok(Self::Serve(config))
+        }
+    }
+}
+
+impl Config
+{
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error>
+    {
+        let path = path.as_ref();
+
+        let text = fs::read_to_string(path)
+            .map_err(|err| Error::Read(path.to_owned(), err))?;
+
+        toml::from_str(&text).map_err(|err| Error::Parse(path.to_owned(), err))
+    }
+
+    // a missing config file just means everything is default
+    pub fn load_optional(path: impl AsRef<Path>) -> Result<Self, Error>
+    {
+        match Self::load(path)
+        {
+            Err(Error::Read(_, err)) if err.kind() == io::ErrorKind::NotFound =>
+            {
+                Ok(Self::default())
+            },
+            x => x
+        }
+    }
+
+    // every listener with the shorthand ones included, defaults to tls on [::]:443
+    pub fn listeners(&self) -> Vec<ListenerConfig>
+    {
+        let mut listeners = self.listen.iter().map(|address|
+        {
+            ListenerConfig::new(address.clone(), ListenerMode::Tls)
+        }).chain(self.listener.iter().cloned()).collect::<Vec<_>>();
+
+        if listeners.is_empty()
+        {
+            listeners.push(ListenerConfig::new("[::]:443".to_owned(), ListenerMode::Tls));
+        }
+
+        let https_port = listeners.iter().find(|listener| listener.mode == ListenerMode::Tls)
+            .and_then(|listener| listener.address.to_socket_addrs().ok()?.next())
+            .map_or(443, |address| address.port());
+
+        listeners.iter_mut().for_each(|listener|
+        {
+            listener.https_port.get_or_insert(https_port);
+        });
+
+        listeners
+    }
+
+    pub fn needs_tls(&self) -> bool
+    {
+        self.listeners().iter().any(|listener| listener.mode == ListenerMode::Tls)
+    }
+
+    // every host name and the ips of the listeners, plus the usual localhost ones
+    pub fn local_names(&self) -> Vec<String>
+    {
+        let mut names = self.host.iter().flat_map(|host| host.names.iter().cloned())
+            .collect::<Vec<_>>();
+
+        let listener_ips = self.listeners().into_iter().filter_map(|listener|
+        {
+            listener.address.parse::<SocketAddr>().ok()
+        }).map(|address| address.ip()).filter(|ip| !ip.is_unspecified());
+
+        let defaults = ["localhost".to_owned(), "127.0.0.1".to_owned(), "::1".to_owned()];
+
+        defaults.into_iter().chain(listener_ips.map(|ip| ip.to_string())).for_each(|name|
+        {
+            if !names.contains(&name)
+            {
+                names.push(name);
+            }
+        });
+
+        names
+    }
+
+    pub fn reload_interval(&self) -> Option<Duration>
+    {
+        (self.tls.reload_interval != 0).then(|| Duration::from_secs(self.tls.reload_interval))
+    }
+
+    pub fn idle_timeout(&self) -> Duration
+    {
+        Duration::from_secs(self.timeouts.idle)
+    }
+
+    pub fn write_timeout(&self) -> Duration
+    {
+        Duration::from_secs(self.timeouts.write)
+    }
+
+    fn apply(&mut self, args: Args)
+    {
+        // listeners on the command line replace the configured ones
+        if !args.listen.is_empty() || !args.http.is_empty()
+        {
+            self.listen = args.listen;
+            self.listener = args.http.into_iter().map(|address|
+            {
+                ListenerConfig::new(address, ListenerMode::Plain)
+            }).collect();
+        }
+
+        if let Some(root) = args.root
+        {
+            self.root = root;
+        }
+
+        if let Some(cert) = args.cert
+        {
+            self.tls.cert = cert;
+        }
+
+        if args.key.is_some()
+        {
+            self.tls.key = args.key;
+        }
+
+        if args.client_ca.is_some()
+        {
+            self.tls.client_ca = args.client_ca;
+        }
+
+        if let Some(idle) = args.idle_timeout
+        {
+            self.timeouts.idle = idle;
+        }
+
+        self.log.requests |= args.log_requests;
+
+        if args.quiet
+        {
+            self.log.connections = false;
+        }
+    }
+
+    fn validate(&self, check_tls_files: bool) -> Result<(), Error>
+    {
+        let listeners = self.listeners();
+        listeners.iter().enumerate().try_for_each(|(index, listener)|
+        {
+            let address = &listener.address;
+
+            if listeners[..index].iter().any(|other| other.address == *address)
+            {
+                return Err(Error::Invalid(format!("{address} is listened on more than once")));
+            }
+
+            address.to_socket_addrs().map(|_| ()).map_err(|err|
+            {
+                Error::Invalid(format!("cant use {address} as a listen address ({err})"))
+            })
+        })?;
+
+        if !self.root.is_dir()
+        {
+            return Err(Error::Invalid(
+                format!("document root {} isnt a directory", self.root.display())
+            ));
+        }
+
+        if self.tls.versions.is_empty()
+        {
+            return Err(Error::Invalid("tls.versions needs at least 1 version".to_owned()));
+        }
+
+        // plain listeners dont need any certificates
+        let tls_files = [
+            Some(&self.tls.cert),
+            self.tls.key.as_ref(),
+            self.tls.ocsp.as_ref(),
+            self.tls.client_ca.as_ref()
+        ];
+        let check_tls_files = check_tls_files && self.needs_tls();
+        tls_files.into_iter().flatten().filter(|_| check_tls_files).try_for_each(|path|
+        {
+            if path.is_file()
+            {
+                Ok(())
+            } else
+            {
+                Err(Error::Invalid(format!("{} cant be found", path.display())))
+            }
+        })?;
+
+        let verifies_clients = self.client_auth.iter().any(|rule|
+        {
+            rule.policy != ClientAuthPolicy::None
+        });
+
+        if verifies_clients && self.tls.client_ca.is_none()
+        {
+            let message = "client_auth rules need a tls.client_ca to verify with";
+
+            return Err(Error::Invalid(message.to_owned()));
+        }
+
+        self.host.iter().try_for_each(|host|
+        {
+            if host.names.is_empty()
+            {
+                return Err(Error::Invalid("every host needs at least 1 name".to_owned()));
+            }
+
+            let name = &host.names[0];
+
+            if (host.key.is_some() || host.ocsp.is_some()) && host.cert.is_none()
+            {
+                return Err(Error::Invalid(
+                    format!("host {name} has a key or ocsp response without a cert")
+                ));
+            }
+
+            if let Some(root) = host.root.as_ref().filter(|root| !root.is_dir())
+            {
+                return Err(Error::Invalid(
+                    format!("document root {} of host {name} isnt a directory", root.display())
+                ));
+            }
+
+            let files = [host.cert.as_ref(), host.key.as_ref(), host.ocsp.as_ref()];
+            files.into_iter().flatten().try_for_each(|path|
+            {
+                if path.is_file()
+                {
+                    Ok(())
+                } else
+                {
+                    Err(Error::Invalid(format!("{} of host {name} cant be found", path.display())))
+                }
+            })
+        })?;
+
+        if self.timeouts.idle == 0 || self.timeouts.write == 0
+        {
+            return Err(Error::Invalid("timeouts have to be at least 1 second".to_owned()));
+        }
+
+        self.redirect.iter().try_for_each(|rule|
+        {
+            if rule.redirect_status().is_none()
+            {
+                return Err(Error::Invalid(
+                    format!("redirect from {} has status {}, expected 301, 302, 307 or 308",
+                        rule.from, rule.status)
+                ));
+            }
+
+            if !rule.from.starts_with('/')
+            {
+                return Err(Error::Invalid(
+                    format!("redirect from {} has to start with a /", rule.from)
+                ));
+            }
+
+            Ok(())
+        })?;
+
+        if !(1..=9).contains(&self.compression.gzip_level)
+        {
+            return Err(Error::Invalid("gzip_level has to be between 1 and 9".to_owned()));
+        }
+
+        if self.compression.brotli_level > 11
+        {
+            return Err(Error::Invalid("brotli_level has to be between 0 and 11".to_owned()));
+        }
+
+        if !(1..=22).contains(&self.compression.zstd_level)
+        {
+            return Err(Error::Invalid("zstd_level has to be between 1 and 22".to_owned()));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Args
+{
+    config: Option<PathBuf>,
+    listen: Vec<String>,
+    http: Vec<String>,
+    root: Option<PathBuf>,
+    cert: Option<PathBuf>,
+    key: Option<PathBuf>,
+    client_ca: Option<PathBuf>,
+    idle_timeout: Option<u64>,
+    log_requests: bool,
+    quiet: bool,
+    generate_cert: bool,
+    cert_names: Vec<String>
+}
+
+impl Args
+{
+    // none means only the help got asked for
+    fn parse(args: impl IntoIterator<Item=String>) -> Result<Option<Self>, Error>
+    {
+        let mut this = Self::default();
+        let mut positional = Vec::new();
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next()
+        {
+            let mut value = ||
+            {
+                args.next().ok_or_else(|| Error::Usage(format!("{arg} needs a value")))
+            };
+
+            match arg.as_str()
+            {
+                "-h" | "--help" => return Ok(None),
+                "-c" | "--config" => this.config = Some(value()?.into()),
+                "-l" | "--listen" => this.listen.push(value()?),
+                "--http" => this.http.push(value()?),
+                "-r" | "--root" => this.root = Some(value()?.into()),
+                "--cert" => this.cert = Some(value()?.into()),
+                "--key" => this.key = Some(value()?.into()),
+                "--client-ca" => this.client_ca = Some(value()?.into()),
+                "--idle-timeout" =>
+                {
+                    let value = value()?;
+                    let seconds = value.parse().map_err(|_|
+                    {
+                        Error::Usage(format!("{value} isnt a valid amount of seconds"))
+                    })?;
+
+                    this.idle_timeout = Some(seconds);
+                },
+                "--log-requests" => this.log_requests = true,
+                "-q" | "--quiet" => this.quiet = true,
+                "--generate-cert" => this.generate_cert = true,
+                "--cert-name" => this.cert_names.push(value()?),
+                x if x.starts_with('-') => return Err(Error::Usage(format!("unknown option {x}"))),
+                _ => positional.push(arg)
+            }
+        }
+
+        // the old way of passing the address and the root still works
+        let mut positional = positional.into_iter();
+
+        if let Some(address) = positional.next()
+        {
+            this.listen.push(address);
+        }
+
+        if let Some(root) = positional.next()
+        {
+            this.root = Some(root.into());
+        }
+
+        if let Some(extra) = positional.next()
+        {
+            return Err(Error::Usage(format!("unexpected argument {extra}")));
+        }
+
+        Ok(Some(this))
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::test_dir::TestDir;
+
+
+    struct ConfigDir(TestDir);
+
+    impl ConfigDir
+    {
+        // a root directory and a cert file to point configs at
+        fn new(name: &str) -> Self
+        {
+            let base = TestDir::new(&format!("config_{name}"));
+
+            fs::create_dir_all(base.join("root")).unwrap();
+            fs::write(base.join("cert.pem"), b"").unwrap();
+
+            Self(base)
+        }
+
+        fn path(&self, name: &str) -> String
+        {
+            self.0.join(name).display().to_string()
+        }
+
+        // the config gets written with the root and cert already filled in
+        fn command(&self, toml: &str, args: &[&str]) -> Result<Command, Error>
+        {
+            let config = format!(
+                "root = {:?}\n{toml}\n[tls]\ncert = {:?}\n",
+                self.path("root"),
+                self.path("cert.pem")
+            );
+
+            fs::write(self.0.join("funserver.toml"), config).unwrap();
+
+            let args = ["--config".to_owned(), self.path("funserver.toml")].into_iter()
+                .chain(args.iter().map(|arg| arg.to_string()));
+
+            Command::from_args(args)
+        }
+
+        fn config(&self, toml: &str, args: &[&str]) -> Config
+        {
+            match self.command(toml, args)
+            {
+                Ok(Command::Serve(config)) => config,
+                Ok(_) => panic!("expected a config"),
+                Err(err) => panic!("{err}")
+            }
+        }
+
+        fn error(&self, toml: &str, args: &[&str]) -> String
+        {
+            match self.command(toml, args)
+            {
+                Ok(_) => panic!("expected an error"),
+                Err(err) => err.to_string()
+            }
+        }
+    }
+
+    fn addresses(config: &Config) -> Vec<(String, ListenerMode, Option<u16>)>
+    {
+        config.listeners().into_iter().map(|listener|
+        {
+            (listener.address, listener.mode, listener.https_port)
+        }).collect()
+    }
+
+    #[test]
+    fn takes_positional_arguments()
+    {
+        let dir = ConfigDir::new("positional");
+
+        let other_root = dir.0.join("other");
+        fs::create_dir_all(&other_root).unwrap();
+
+        let config = dir.config("", &["127.0.0.1:8443", &other_root.display().to_string()]);
+
+        assert_eq!(config.listen, ["127.0.0.1:8443"]);
+        assert_eq!(config.root, other_root);
+
+        let config = dir.config("", &["127.0.0.1:8443"]);
+        assert_eq!(config.root, dir.0.join("root"));
+
+        let config = dir.config("", &[]);
+        assert_eq!(addresses(&config), [("[::]:443".to_owned(), ListenerMode::Tls, Some(443))]);
+
+        let error = dir.error("", &["127.0.0.1:1", ".", "extra"]);
+        assert!(error.starts_with("unexpected argument extra"), "{error}");
+
+        assert!(matches!(Command::from_args(["-h".to_owned()]), Ok(Command::Help)));
+        let help = Command::from_args(["x".to_owned(), "--help".to_owned()]);
+        assert!(matches!(help, Ok(Command::Help)));
+    }
+
+    #[test]
+    fn applies_overrides()
+    {
+        let dir = ConfigDir::new("overrides");
+
+        let toml = "listen = [\"127.0.0.1:1443\"]\n\
+            [[listener]]\naddress = \"127.0.0.1:1080\"\nmode = \"plain\"\n\
+            [timeouts]\nidle = 7\n[log]\nconnections = true";
+
+        let config = dir.config(toml, &[]);
+        assert_eq!(config.timeouts.idle, 7);
+        assert_eq!(config.timeouts.write, 30);
+        assert_eq!(addresses(&config), [
+            ("127.0.0.1:1443".to_owned(), ListenerMode::Tls, Some(1443)),
+            ("127.0.0.1:1080".to_owned(), ListenerMode::Plain, Some(1443))
+        ]);
+
+        let args = [
+            "--http", "127.0.0.1:8080",
+            "--idle-timeout", "9",
+            "-q",
+            "--log-requests",
+            "--key", "key.pem"
+        ];
+
+        // command line listeners replace all of the configured ones
+        let config = dir.config(toml, &args);
+        let plain = ("127.0.0.1:8080".to_owned(), ListenerMode::Plain, Some(443));
+        assert_eq!(addresses(&config), [plain]);
+        assert!(!config.needs_tls());
+        assert_eq!(config.timeouts.idle, 9);
+        assert!(!config.log.connections);
+        assert!(config.log.requests);
+        assert_eq!(config.tls.key, Some(PathBuf::from("key.pem")));
+
+        let config = dir.config(toml, &["-l", "127.0.0.1:2443", "--listen", "127.0.0.1:3443"]);
+        assert_eq!(config.listen, ["127.0.0.1:2443", "127.0.0.1:3443"]);
+        assert!(config.listener.is_empty());
+
+        let error = dir.error("", &["--idle-timeout", "soon"]);
+        assert!(error.starts_with("soon isnt a valid amount of seconds"), "{error}");
+
+        let error = dir.error("", &["--root"]);
+        assert!(error.starts_with("--root needs a value"), "{error}");
+
+        let error = dir.error("", &["--nope"]);
+        assert!(error.starts_with("unknown option --nope"), "{error}");
+    }
+
+    #[test]
+    fn defaults_https_ports()
+    {
+        let dir = ConfigDir::new("https_port");
+
+        let redirect = "[[listener]]\naddress = \"127.0.0.1:1080\"\nmode = \"redirect\"\n";
+
+        // no tls listener at all so it has to be the usual port
+        let config = dir.config(redirect, &[]);
+        let redirecting = ("127.0.0.1:1080".to_owned(), ListenerMode::Redirect, Some(443));
+        assert_eq!(addresses(&config), [redirecting]);
+
+        let toml = format!("{redirect}[[listener]]\naddress = \"127.0.0.1:8443\"\n");
+        let config = dir.config(&toml, &[]);
+        assert_eq!(addresses(&config)[0].2, Some(8443));
+
+        let toml = format!("listen = [\"127.0.0.1:9443\"]\n{redirect}https_port = 4443\n");
+        let config = dir.config(&toml, &[]);
+        let redirecting = ("127.0.0.1:1080".to_owned(), ListenerMode::Redirect, Some(4443));
+        assert_eq!(addresses(&config)[1], redirecting);
+    }
+
+    #[test]
+    fn rejects_invalid_configs()
+    {
+        let dir = ConfigDir::new("invalid");
+
+        let invalid = |toml: &str, args: &[&str], expected: &str|
+        {
+            let error = dir.error(toml, args);
+            assert!(error.contains(expected), "{error} doesnt contain {expected}");
+        };
+
+        let duplicated = "listen = [\"127.0.0.1:1\", \"127.0.0.1:1\"]";
+        invalid(duplicated, &[], "listened on more than once");
+        invalid("listen = [\"not an address\"]", &[], "cant use not an address");
+        invalid("", &["--root", &dir.path("cert.pem")], "isnt a directory");
+        invalid("", &["--cert", &dir.path("missing.pem")], "missing.pem cant be found");
+        invalid("", &["--client-ca", &dir.path("ca.pem")], "ca.pem cant be found");
+        invalid("[[client_auth]]\npattern = \"*\"\npolicy = \"required\"", &[], "need a tls.client_ca");
+        invalid("[timeouts]\nidle = 0", &[], "at least 1 second");
+        invalid("[[redirect]]\nfrom = \"/a\"\nto = \"/b\"\nstatus = 303", &[], "has status 303");
+        invalid("[[redirect]]\nfrom = \"a\"\nto = \"/b\"", &[], "has to start with a /");
+        invalid("[compression]\ngzip_level = 0", &[], "gzip_level");
+        invalid("[compression]\nbrotli_level = 12", &[], "brotli_level");
+        invalid("[compression]\nzstd_level = 23", &[], "zstd_level");
+        invalid("[[host]]\nnames = []", &[], "at least 1 name");
+        invalid("[[host]]\nnames = [\"a\"]\nkey = \"k.pem\"", &[], "without a cert");
+        invalid("unknown = 1", &[], "unknown field");
+        invalid("[post]\nforward = [\"http://a\"]", &[], "has to start with https://");
+
+        // plain listeners never need the certificate to exist
+        let missing = ["--http", "127.0.0.1:8080", "--cert", "missing.pem"];
+        assert!(matches!(dir.command("", &missing), Ok(Command::Serve(_))));
+
+        let error = match Command::from_args(["-c".to_owned(), dir.path("nope.toml")])
+        {
+            Ok(_) => panic!("expected an error"),
+            Err(err) => err.to_string()
+        };
+
+        assert!(error.starts_with("error reading config at"), "{error}");
+    }
+
+    #[test]
+    fn generates_certs_for_local_names()
+    {
+        let dir = ConfigDir::new("generate_cert");
+
+        let toml = "listen = [\"192.168.1.10:8443\", \"[::]:9443\"]\n\
+            [[host]]\nnames = [\"a.test\"]";
+        let args = ["--generate-cert", "--cert", "missing.pem"];
+
+        // the cert doesnt exist yet since its about to be made
+        let Ok(Command::GenerateCert(_, names)) = dir.command(toml, &args) else
+        {
+            panic!("expected cert generation")
+        };
+
+        assert_eq!(names, ["a.test", "localhost", "127.0.0.1", "::1", "192.168.1.10"]);
+
+        let args = ["--generate-cert", "--cert-name", "b.test", "--cert-name", "10.0.0.1"];
+        let Ok(Command::GenerateCert(_, names)) = dir.command(toml, &args) else
+        {
+            panic!("expected cert generation")
+        };
+
+        assert_eq!(names, ["b.test", "10.0.0.1"]);
+    }
+
+    #[test]
+    fn parses_readme_example()
+    {
+        let readme = include_str!("../README.md");
+
+        let (_, example) = readme.split_once("```toml\n").unwrap();
+        let (example, _) = example.split_once("```").unwrap();
+
+        let config: Config = toml::from_str(example).unwrap();
+
+        assert!(config.directory_listing);
+        assert_eq!(config.root, PathBuf::from("public"));
+        assert_eq!(config.listener.len(), 2);
+        assert_eq!(config.host.len(), 1);
+        assert_eq!(config.host[0].directory_listing, Some(true));
+        assert_eq!(config.cache_control.len(), 2);
+        assert_eq!(config.redirect.len(), 2);
+        assert_eq!(config.client_auth.len(), 1);
+    }
+}
@@ -0,0 +1,334 @@
+use std::{
+    thread,
+    sync::Arc,
+    io::{self, Read},
+    time::{Duration, Instant},
+    net::{TcpListener, TcpStream, SocketAddr, ToSocketAddrs}
+};
+
+use rustls::{ServerConnection, server::ServerConfig};
+
+use socket2::{Socket, Domain, Type, Protocol};
+
+use crate::{
+    AutoError,
+    tls::{self, TlsHandle},
+    server::{VirtualHosts, SmolServer},
+    config::{ListenerConfig, ListenerMode}
+};
+
+
+const BACKLOG: i32 = 128;
+
+pub struct ConnectionOptions
+{
+    pub idle_timeout: Duration,
+    pub write_timeout: Duration,
+    pub log_connections: bool
+}
+
+// everything the connections of a listener share
+#[derive(Clone)]
+pub struct Shared
+{
+    pub cfg: Option<Arc<TlsHandle>>,
+    pub hosts: Arc<VirtualHosts>,
+    pub options: Arc<ConnectionOptions>
+}
+
+pub struct Listener
+{
+    config: Arc<ListenerConfig>,
+    address: SocketAddr,
+    socket: TcpListener
+}
+
+impl Listener
+{
+    // binds every listener on every address it resolves to
+    pub fn bind_all(configs: Vec<ListenerConfig>) -> Result<Vec<Self>, AutoError>
+    {
+        let resolved = configs.into_iter().map(|config|
+        {
+            let addresses = config.address.to_socket_addrs().map_err(|err|
+            {
+                format!("cant resolve {} ({err})", config.address)
+            })?.collect::<Vec<_>>();
+
+            Ok((Arc::new(config), addresses))
+        }).collect::<Result<Vec<_>, AutoError>>()?;
+
+        let all_addresses = resolved.iter().flat_map(|(_, addresses)| addresses.iter().copied())
+            .collect::<Vec<_>>();
+
+        resolved.iter().flat_map(|(config, addresses)|
+        {
+            addresses.iter().map(|address| (Arc::clone(config), *address))
+        }).map(|(config, address)|
+        {
+            // ipv6 sockets take ipv4 connections too unless theres a separate ipv4 one
+            let ipv6_only = config.ipv6_only.unwrap_or_else(||
+            {
+                all_addresses.iter().any(|other| other.is_ipv4() && other.port() == address.port())
+            });
+
+            let socket = Self::bind_socket(address, ipv6_only).map_err(|err|
+            {
+                format!("bind error ({address}): {err}")
+            })?;
+
+            Ok(Self{config, address, socket})
+        }).collect()
+    }
+
+    fn bind_socket(address: SocketAddr, ipv6_only: bool) -> io::Result<TcpListener>
+    {
+        let socket = Socket::new(Domain::for_address(address), Type::STREAM, Some(Protocol::TCP))?;
+
+        if address.is_ipv6()
+        {
+            socket.set_only_v6(ipv6_only)?;
+        }
+
+        // same as what std does, lets restarts bind while old connections are still closing
+        #[cfg(unix)]
+        socket.set_reuse_address(true)?;
+
+        socket.bind(&address.into())?;
+        socket.listen(BACKLOG)?;
+
+        Ok(socket.into())
+    }
+
+    pub fn describe(&self) -> String
+    {
+        let mode = match self.config.mode
+        {
+            ListenerMode::Tls => "tls",
+            ListenerMode::Plain => "plain",
+            ListenerMode::Redirect => "redirect"
+        };
+
+        match &self.config.name
+        {
+            Some(name) => format!("{} ({name}, {mode})", self.address),
+            None => format!("{} ({mode})", self.address)
+        }
+    }
+
+    pub fn spawn(self, shared: Shared) -> thread::JoinHandle<()>
+    {
+        thread::spawn(move || self.accept_loop(shared))
+    }
+
+    fn accept_loop(self, shared: Shared)
+    {
+        for stream in self.socket.incoming()
+        {
+            let config = Arc::clone(&self.config);
+            let shared = shared.clone();
+            thread::spawn(move ||
+            {
+                match stream
+                {
+                    Err(err) =>
+                    {
+                        println!("listener error: {err}");
+                    },
+                    Ok(stream) =>
+                    {
+                        if let Err(err) = client_handler(&config, shared, stream)
+                        {
+                            println!("{}", *err);
+                        }
+                    }
+                }
+            });
+        }
+    }
+}
+
+fn client_handler(
+    listener: &ListenerConfig,
+    shared: Shared,
+    stream: TcpStream
+) -> Result<(), AutoError>
+{
+    let options = &shared.options;
+
+    // without a read timeout a quiet client would block the read forever
+    stream.set_read_timeout(Some(options.idle_timeout))?;
+    stream.set_write_timeout(Some(options.write_timeout))?;
+
+    let mut server = SmolServer::new(shared.hosts);
+
+    if options.log_connections
+    {
+        let name = listener.name.as_deref().unwrap_or(&listener.address);
+
+        println!("connection created (peer: {:?}, listener: {name})", stream.peer_addr());
+    }
+
+    let result = match listener.mode
+    {
+        ListenerMode::Tls =>
+        {
+            let cfg = shared.cfg.expect("tls gets loaded when theres a tls listener").get();
+
+            tls_connection(cfg, &mut server, options, stream)
+        },
+        ListenerMode::Plain => plain_connection(&mut server, stream),
+        ListenerMode::Redirect =>
+        {
+            let port = listener.https_port.expect("listeners always have an https port");
+
+            plain_connection(&mut server.redirecting_to_https(port), stream)
+        }
+    };
+
+    if options.log_connections
+    {
+        println!("connection killed");
+    }
+
+    result
+}
+
+fn tls_connection(
+    cfg: Arc<ServerConfig>,
+    server: &mut SmolServer,
+    options: &ConnectionOptions,
+    mut stream: TcpStream
+) -> Result<(), AutoError>
+{
+    let mut tls_conn = ServerConnection::new(cfg)?;
+
+    let mut handshake_done = false;
+
+    let mut last_change = Instant::now();
+    loop
+    {
+        let mut progressed = false;
+
+        if (Instant::now() - last_change) > options.idle_timeout
+        {
+            let _ = server.timeout(rustls::Stream::new(&mut tls_conn, &mut stream));
+
+            break;
+        }
+
+        if tls_conn.wants_read()
+        {
+            match tls_conn.read_tls(&mut stream)
+            {
+                // the client closed the connection
+                Ok(0) => break,
+                Ok(_) => (),
+                Err(err) if is_timeout(&err) => continue,
+                Err(err) => return Err(AutoError::from(err))
+            }
+
+            let io_state = tls_conn.process_new_packets()?;
+
+            // the client certificate (if any) and the protocol are known after the handshake
+            if !handshake_done && !tls_conn.is_handshaking()
+            {
+                let client = tls_conn.peer_certificates().and_then(|certs| certs.first())
+                    .map(|cert| tls::client_identity(cert));
+
+                server.set_client(client);
+
+                if options.log_connections
+                {
+                    let version = tls_conn.protocol_version().and_then(|x| x.as_str());
+                    let suite = tls_conn.negotiated_cipher_suite().and_then(|x| x.suite().as_str());
+                    let alpn = tls_conn.alpn_protocol().map(String::from_utf8_lossy);
+
+                    println!(
+                        "tls established (version: {}, cipher suite: {}, protocol: {})",
+                        version.unwrap_or("unknown"),
+                        suite.unwrap_or("unknown"),
+                        alpn.as_deref().unwrap_or("http/1.1")
+                    );
+                }
+
+                if tls_conn.alpn_protocol() == Some(b"h2")
+                {
+                    server.use_http2(options.log_connections);
+                }
+
+                handshake_done = true;
+            }
+
+            if io_state.plaintext_bytes_to_read() > 0
+            {
+                let amount = io_state.plaintext_bytes_to_read();
+                let mut read_bytes = vec![0;amount];
+
+                match tls_conn.reader().read_exact(&mut read_bytes)
+                {
+                    Ok(_) => (),
+                    Err(err) if err.kind()==io::ErrorKind::WouldBlock => (),
+                    Err(err) => return Err(AutoError::from(err))
+                }
+
+                let mut wrapper = rustls::Stream::new(&mut tls_conn, &mut stream);
+                server.respond(&read_bytes, &mut wrapper)?;
+            }
+
+            last_change = Instant::now();
+            progressed = true;
+        }
+
+        if tls_conn.wants_write()
+        {
+            tls_conn.write_tls(&mut stream)?;
+
+            last_change = Instant::now();
+            progressed = true;
+        }
+
+        if !server.alive()
+        {
+            break;
+        }
+
+        // waiting only when nothing happened keeps http2 flow control round trips quick
+        if !progressed
+        {
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    Ok(())
+}
+
+fn plain_connection(server: &mut SmolServer, mut stream: TcpStream) -> Result<(), AutoError>
+{
+    let mut buffer = vec![0; 4096];
+
+    while server.alive()
+    {
+        match stream.read(&mut buffer)
+        {
+            Ok(0) => break,
+            Ok(amount) => server.respond(&buffer[..amount], &mut stream)?,
+            // the read timeout is the idle timeout so nothing happened for that long
+            Err(err) if is_timeout(&err) =>
+            {
+                let _ = server.timeout(&mut stream);
+
+                break;
+            },
+            Err(err) => return Err(AutoError::from(err))
+        }
+    }
+
+    Ok(())
+}
+
+fn is_timeout(err: &io::Error) -> bool
+{
+    // which one it is depends on the platform
+    matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+}
@@ -1,28 +1,24 @@
 use std::{
-    fs,
     fmt,
     env,
-    thread,
+    process,
     ops::Deref,
-    sync::Arc,
-    io::{self, Read},
-    time::{Duration, Instant},
-    net::{TcpListener, TcpStream}
+    sync::Arc
 };
 
-use rustls::{
-    self,
-    ServerConnection,
-    server::ServerConfig,
-    pki_types::PrivateKeyDer
-};
-
-use rustls_pemfile::Item;
-
 use server::*;
+use config::Command;
+use listener::{Listener, Shared, ConnectionOptions};
+use tls::{TlsSettings, TlsHandle};
 
 
 mod server;
+mod config;
+mod listener;
+mod tls;
+
+#[cfg(test)]
+mod test_dir;
 
 struct AutoError
 {
@@ -47,114 +43,130 @@ impl<T: fmt::Display> From<T> for AutoError
     }
 }
 
-fn client_handler(cfg: Arc<ServerConfig>, mut stream: TcpStream) -> Result<(), AutoError>
+fn run() -> Result<(), AutoError>
 {
-    let mut tls_conn = ServerConnection::new(cfg)?;
-    let mut server = SmolServer::new();
-
-    println!("connection created (peer: {:?})", stream.peer_addr());
-
-    let mut last_change = Instant::now();
-    loop
+    let mut config = match Command::from_args(env::args().skip(1))?
     {
-        if (Instant::now() - last_change) > Duration::from_secs(5)
+        Command::Serve(config) => config,
+        Command::Help =>
         {
-            break;
-        }
+            println!("{}", config::USAGE);
 
-        if tls_conn.wants_read()
+            return Ok(());
+        },
+        Command::GenerateCert(config, names) =>
         {
-            tls_conn.read_tls(&mut stream)?;
+            let fingerprint = tls::generate_self_signed(
+                &names,
+                &config.tls.cert,
+                config.tls.key.as_deref()
+            )?;
 
-            let io_state = tls_conn.process_new_packets()?;
-            if io_state.plaintext_bytes_to_read() > 0
-            {
-                let amount = io_state.plaintext_bytes_to_read();
-                let mut read_bytes = vec![0;amount];
+            let path = config.tls.cert.display();
+            println!("generated a certificate for {} at {path}", names.join(", "));
+            println!("sha256 fingerprint: {fingerprint}");
+
+            return Ok(());
+        }
+    };
+
+    config.log.requests |= env::var("PRINT_REQUESTS").is_ok();
 
-                match tls_conn.reader().read_exact(&mut read_bytes)
-                {
-                    Ok(_) => (),
-                    Err(err) if err.kind()==io::ErrorKind::WouldBlock => (),
-                    Err(err) => return Err(AutoError::from(err))
-                }
+    let options = Arc::new(ConnectionOptions{
+        idle_timeout: config.idle_timeout(),
+        write_timeout: config.write_timeout(),
+        log_connections: config.log.connections
+    });
 
-                let mut wrapper = rustls::Stream::new(&mut tls_conn, &mut stream);
-                server.respond(&read_bytes, &mut wrapper)?;
-            }
+    // plain listeners can work without any certificates
+    let cfg = if config.needs_tls()
+    {
+        let tls_settings = TlsSettings::from_config(&config)?;
+        let handle = Arc::new(TlsHandle::new(tls_settings.server_config()?));
+
+        tls::watch(Arc::clone(&handle), tls_settings, config.reload_interval())?;
+
+        Some(handle)
+    } else
+    {
+        None
+    };
+
+    let listeners = config.listeners();
+
+    let mut settings = Settings::new(&config.root).map_err(|err|
+    {
+        format!("document root error ({}): {err}", config.root.display())
+    })?;
+
+    settings.cache_control = config.cache_control;
+    settings.redirects = config.redirect;
+    settings.client_auth = config.client_auth;
+    settings.compression = config.compression;
+    settings.post_forward = config.post.forward;
+    settings.directory_listing = config.directory_listing;
+    settings.log_requests = config.log.requests;
+
+    let mut hosts = VirtualHosts::new(settings);
+
+    config.host.into_iter().try_for_each(|host|
+    {
+        let default = hosts.default_settings();
 
-            last_change = Instant::now();
+        let mut settings = match &host.root
+        {
+            Some(root) => default.with_root(root).map_err(|err|
+            {
+                format!("document root error ({}): {err}", root.display())
+            })?,
+            None => default.clone()
+        };
+
+        if let Some(post) = host.post
+        {
+            settings.post_forward = post.forward;
         }
 
-        if tls_conn.wants_write()
+        if let Some(directory_listing) = host.directory_listing
         {
-            tls_conn.write_tls(&mut stream)?;
-            
-            last_change = Instant::now();
+            settings.directory_listing = directory_listing;
         }
 
-        if !server.alive()
+        hosts.add(host.names, settings);
+
+        Ok::<_, AutoError>(())
+    })?;
+
+    let hosts = Arc::new(hosts);
+
+    let listeners = Listener::bind_all(listeners)?;
+
+    let shared = Shared{cfg, hosts, options};
+
+    let accept_threads = listeners.into_iter().map(|listener|
+    {
+        if shared.options.log_connections
         {
-            break;
+            println!("listening on {}", listener.describe());
         }
 
-        thread::sleep(Duration::from_millis(100));
-    }
+        listener.spawn(shared.clone())
+    }).collect::<Vec<_>>();
 
-    println!("connection killed");
+    accept_threads.into_iter().for_each(|handle|
+    {
+        let _ = handle.join();
+    });
 
     Ok(())
 }
 
 fn main()
 {
-    let address = env::args().nth(1).unwrap_or_else(|| "[::]:443".to_owned());
-
-    let listener = TcpListener::bind(address)
-        .unwrap_or_else(|err|
-        {
-            panic!("bind error: {}", err);
-        });
-
-    let cert_raw = fs::read("cert.pem").expect("cert.pem cant be found");
-    let mut cert_raw = &cert_raw[..];
-
-    let (cert, cert_key) = rustls_pemfile::read_all(&mut cert_raw)
-        .into_iter().map(|x| x.unwrap()).fold((None, None), |(cert, key), item|
-        {
-            match item
-            {
-                Item::X509Certificate(new_cert) => (Some(new_cert), key),
-                Item::Pkcs1Key(new_key) => (cert, Some(PrivateKeyDer::Pkcs1(new_key))),
-                Item::Pkcs8Key(new_key) => (cert, Some(PrivateKeyDer::Pkcs8(new_key))),
-                _ => (cert, key)
-            }
-        });
-
-    let cfg = Arc::new(ServerConfig::builder()
-        .with_no_client_auth()
-        .with_single_cert(vec![cert.unwrap()], cert_key.unwrap())
-        .expect("error creating certificate"));
-
-    for stream in listener.incoming()
+    if let Err(err) = run()
     {
-        let cfg = Arc::clone(&cfg);
-        thread::spawn(move ||
-        {
-            match stream
-            {
-                Err(err) =>
-                {
-                    println!("listener error: {err}");
-                },
-                Ok(stream) =>
-                {
-                    if let Err(err) = client_handler(cfg, stream)
-                    {
-                        println!("{}", *err);
-                    }
-                }
-            }
-        });
+        eprintln!("{}", *err);
+
+        process::exit(1);
     }
 }
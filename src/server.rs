@@ -1,27 +1,72 @@
 use std::{
-    fs,
     io,
     fmt,
-    env,
+    sync::Arc,
     path::{Path, PathBuf},
     io::Write
 };
 
-pub use http::{RequestType, PartialRequest, Request, Status, ContentType};
-use http::RequestState;
+pub use http::{
+    ClientIdentity,
+    RequestType,
+    RequestTarget,
+    PartialRequest,
+    Request,
+    Response,
+    Status,
+    ContentType
+};
+
+use serde::Deserialize;
+
+pub use compression::CompressionSettings;
+pub use post::ForwardTarget;
 
 pub mod http;
+mod http2;
+mod hpack;
 mod post;
+mod files;
+mod compression;
 
 
 #[allow(dead_code)]
+#[derive(Debug)]
 pub enum Error
 {
     HttpError(http::Error),
     Unimplemented,
     WritingError(io::Error),
     TlsError(rustls::Error),
-    DirectoryError
+    DirectoryError,
+    Forbidden,
+    // the response head already went out so theres no answering with anything else
+    Aborted(io::Error)
+}
+
+impl Error
+{
+    pub fn status(&self) -> Status
+    {
+        match self
+        {
+            Error::HttpError(err) => err.status(),
+            Error::Unimplemented => Status::NotImplemented,
+            Error::WritingError(err) =>
+            {
+                match err.kind()
+                {
+                    io::ErrorKind::NotFound => Status::NotFound,
+                    io::ErrorKind::PermissionDenied => Status::Forbidden,
+                    _ => Status::InternalServerError
+                }
+            },
+            Error::TlsError(_) => Status::BadGateway,
+            Error::DirectoryError => Status::BadRequest,
+            Error::Forbidden => Status::Forbidden,
+            Error::Aborted(_) => Status::InternalServerError
+        }
+    }
 }
 
 impl From<io::Error> for Error
@@ -67,25 +112,324 @@ impl fmt::Display for Error
             {
                 return write!(f, "tls error ({err})");
             },
-            Error::DirectoryError => "invalid path".to_owned()
+            Error::DirectoryError => "invalid path".to_owned(),
+            Error::Forbidden => "path outside of the document root".to_owned(),
+            Error::Aborted(err) =>
+            {
+                return write!(f, "response aborted ({err})");
+            }
         };
 
         write!(f, "{}", error_text)
     }
 }
 
+const ALLOWED_METHODS: [RequestType; 4] = [
+    RequestType::Get,
+    RequestType::Head,
+    RequestType::Post,
+    RequestType::Options
+];
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CacheRule
+{
+    pub pattern: String,
+    pub value: String
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RedirectRule
+{
+    pub from: String,
+    pub to: String,
+    #[serde(default = "RedirectRule::default_status")]
+    pub status: u16,
+    // prefix rules also match everything below from and keep the rest of the path
+    #[serde(default)]
+    pub prefix: bool
+}
+
+impl RedirectRule
+{
+    fn default_status() -> u16
+    {
+        301
+    }
+
+    pub fn redirect_status(&self) -> Option<Status>
+    {
+        match self.status
+        {
+            301 => Some(Status::MovedPermanently),
+            302 => Some(Status::Found),
+            307 => Some(Status::TemporaryRedirect),
+            308 => Some(Status::PermanentRedirect),
+            _ => None
+        }
+    }
+
+    fn location(&self, path: &str) -> Option<String>
+    {
+        if !self.prefix
+        {
+            return (path == self.from).then(|| self.to.clone());
+        }
+
+        let rest = path.strip_prefix(self.from.trim_end_matches('/'))?;
+
+        // /docs shouldnt match /docsfoo
+        let boundary = rest.is_empty() || rest.starts_with('/');
+
+        boundary.then(|| self.to.trim_end_matches('/').to_owned() + &http::percent_encode_path(rest))
+            .map(|location| if location.is_empty() { "/".to_owned() } else { location })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClientAuthPolicy
+{
+    // no verified client certificate means 403
+    Required,
+    // handlers get the identity if theres one
+    Optional,
+    // handlers never see the identity
+    None
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClientAuthRule
+{
+    pub pattern: String,
+    pub policy: ClientAuthPolicy
+}
+
+#[derive(Clone)]
+pub struct Settings
+{
+    pub root: PathBuf,
+    pub cache_control: Vec<CacheRule>,
+    pub redirects: Vec<RedirectRule>,
+    pub client_auth: Vec<ClientAuthRule>,
+    pub compression: CompressionSettings,
+    pub post_forward: Vec<ForwardTarget>,
+    pub directory_listing: bool,
+    pub log_requests: bool
+}
+
+impl Settings
+{
+    pub fn new(root: impl AsRef<Path>) -> io::Result<Self>
+    {
+        Ok(Self{
+            root: root.as_ref().canonicalize()?,
+            cache_control: Vec::new(),
+            redirects: Vec::new(),
+            client_auth: Vec::new(),
+            compression: CompressionSettings::default(),
+            post_forward: Vec::new(),
+            directory_listing: false,
+            log_requests: false
+        })
+    }
+
+    // the same settings for a different document root
+    pub fn with_root(&self, root: impl AsRef<Path>) -> io::Result<Self>
+    {
+        Ok(Self{root: root.as_ref().canonicalize()?, ..self.clone()})
+    }
+
+    // first matching rule wins
+    pub fn cache_control(&self, path: &str) -> Option<&str>
+    {
+        self.cache_control.iter().find(|rule| pattern_matches(&rule.pattern, path))
+            .map(|rule| rule.value.as_str())
+    }
+
+    // first matching rule wins, paths without a rule are optional
+    pub fn client_auth_policy(&self, path: &str) -> ClientAuthPolicy
+    {
+        self.client_auth.iter().find(|rule| pattern_matches(&rule.pattern, path))
+            .map_or(ClientAuthPolicy::Optional, |rule| rule.policy)
+    }
+
+    // first matching rule wins, the query gets carried over to the new location
+    pub fn redirect(&self, target: &RequestTarget) -> Option<(Status, String)>
+    {
+        let path = target.normalized_path()?;
+
+        self.redirects.iter().find_map(|rule|
+        {
+            let location = rule.location(&path)?;
+
+            Some((rule.redirect_status()?, location + target.query_suffix()))
+        })
+    }
+
+    // resolves a request target into a path inside the document root
+    pub fn resolve_path(&self, target: &RequestTarget) -> Result<PathBuf, Error>
+    {
+        let segments = target.normalized.as_ref().ok_or(Error::Forbidden)?;
+
+        let mut relative = PathBuf::new();
+        for segment in segments
+        {
+            if segment.contains(['/', '\\', '\0'])
+            {
+                return Err(Error::Forbidden);
+            }
+
+            relative.push(segment);
+        }
+
+        self.confine(self.root.join(relative))
+    }
+
+    // symlinks can still point anywhere so check where a path actually ends up
+    pub fn confine(&self, path: PathBuf) -> Result<PathBuf, Error>
+    {
+        match path.canonicalize()
+        {
+            Ok(canonical) if canonical.starts_with(&self.root) => Ok(canonical),
+            Ok(_) => Err(Error::Forbidden),
+            Err(_) => Ok(path)
+        }
+    }
+}
+
+// * matches any amount of any characters, everything else has to match exactly
+pub fn pattern_matches(pattern: &str, text: &str) -> bool
+{
+    let mut parts = pattern.split('*');
+
+    let first = parts.next().expect("split always has a first element");
+    let Some(mut rest) = text.strip_prefix(first) else { return false };
+
+    let mut parts = parts.peekable();
+    while let Some(part) = parts.next()
+    {
+        if parts.peek().is_none()
+        {
+            return rest.ends_with(part);
+        }
+
+        match rest.find(part)
+        {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false
+        }
+    }
+
+    rest.is_empty()
+}
+
+// host names can be exact or start with *. to match any single subdomain
+pub fn host_matches(pattern: &str, host: &str) -> bool
+{
+    let host = host.trim_end_matches('.');
+
+    match pattern.strip_prefix("*.")
+    {
+        Some(domain) =>
+        {
+            host.split_once('.').is_some_and(|(label, rest)|
+            {
+                !label.is_empty() && rest.eq_ignore_ascii_case(domain)
+            })
+        },
+        None => pattern.eq_ignore_ascii_case(host)
+    }
+}
+
+// picks which sites settings a request uses by its host header
+pub struct VirtualHosts
+{
+    default: Arc<Settings>,
+    hosts: Vec<(Vec<String>, Arc<Settings>)>
+}
+
+impl VirtualHosts
+{
+    pub fn new(default: Settings) -> Self
+    {
+        Self{default: Arc::new(default), hosts: Vec::new()}
+    }
+
+    pub fn default_settings(&self) -> &Settings
+    {
+        &self.default
+    }
+
+    pub fn add(&mut self, names: Vec<String>, settings: Settings)
+    {
+        self.hosts.push((names, Arc::new(settings)));
+    }
+
+    // first matching host wins, unknown hosts get the default site
+    pub fn select(&self, host: Option<&str>) -> &Arc<Settings>
+    {
+        host.and_then(|host|
+        {
+            self.hosts.iter().find(|(names, _)|
+            {
+                names.iter().any(|name| host_matches(name, host))
+            }).map(|(_, settings)| settings)
+        }).unwrap_or(&self.default)
+    }
+}
+
 pub struct SmolServer
 {
-    partial: Option<Request>,
-    request_state: RequestState,
+    hosts: Arc<VirtualHosts>,
+    // the settings of whatever host the current request is for
+    settings: Arc<Settings>,
+    partial: PartialRequest,
+    client: Option<ClientIdentity>,
+    // set once the client picked http2, requests come out of its streams instead
+    http2: Option<http2::Connection>,
+    https_redirect: Option<u16>,
+    is_head: bool,
     alive: bool
 }
 
 impl SmolServer
 {
-    pub fn new() -> Self
+    pub fn new(hosts: Arc<VirtualHosts>) -> Self
+    {
+        SmolServer{
+            settings: Arc::clone(&hosts.default),
+            hosts,
+            partial: PartialRequest::new(),
+            client: None,
+            http2: None,
+            https_redirect: None,
+            is_head: false,
+            alive: true
+        }
+    }
+
+    // sends every request over to https on this port instead of answering it
+    pub fn redirecting_to_https(mut self, port: u16) -> Self
     {
-        SmolServer{alive: true, partial: None, request_state: RequestState::default()}
+        self.https_redirect = Some(port);
+
+        self
+    }
+
+    // the verified certificate of the client on the other end of the connection
+    pub fn set_client(&mut self, client: Option<ClientIdentity>)
+    {
+        self.client = client;
+    }
+
+    // the connection speaks http2 from now on (after alpn picked it)
+    pub fn use_http2(&mut self, log_errors: bool)
+    {
+        self.http2 = Some(http2::Connection::new(log_errors));
     }
 
     pub fn extension_content_type(path: impl AsRef<Path>) -> Result<ContentType, Error>
@@ -95,90 +439,305 @@ impl SmolServer
             Ok(http::ContentType::create(extension.to_str().ok_or(Error::DirectoryError)?))
         } else
         {
-            Ok(http::ContentType::Txt)
+            Ok(http::ContentType::OctetStream)
         }
     }
 
-    pub fn relative_path(path: impl AsRef<Path>) -> Result<PathBuf, Error>
+    pub fn respond(
+        &mut self,
+        request: &[u8],
+        mut writer: impl Write
+    ) -> Result<(), Error>
     {
-        let path = path.as_ref();
+        if let Some(mut connection) = self.http2.take()
+        {
+            let result = self.respond_http2(&mut connection, request, &mut writer);
 
-        let current_folder = env::current_dir().map_err(|_| Error::DirectoryError)?;
+            // errors in single streams dont matter, only the connection going away does
+            self.alive = result.is_ok() && !connection.is_closed();
+            self.http2 = Some(connection);
 
-        let path = [current_folder.to_str().ok_or(Error::DirectoryError)?,
-            path.to_str().ok_or(Error::DirectoryError)?].concat();
+            return result;
+        }
 
-        Ok(PathBuf::from(path))
+        self.respond_inner(request, &mut writer).inspect_err(|err|
+        {
+            // another response would just end up in the middle of the last ones body
+            if let Error::Aborted(_) = err
+            {
+                self.alive = false;
+
+                return;
+            }
+
+            let response = self.status_response(err.status());
+
+            // the connection gets closed after this anyway so failing to send it is fine
+            let _ = self.send(&mut writer, response);
+        })
     }
 
-    pub fn respond(
+    // lets the client know that it took too long to finish its request
+    pub fn timeout(&mut self, mut writer: impl Write) -> Result<(), Error>
+    {
+        if let Some(connection) = self.http2.as_mut()
+        {
+            connection.close(&mut writer)?;
+
+            return Ok(());
+        }
+
+        if self.partial.is_pending()
+        {
+            let response = self.status_response(Status::RequestTimeout);
+            self.send(&mut writer, response)?;
+        }
+
+        Ok(())
+    }
+
+    fn respond_inner(
         &mut self,
         request: &[u8],
         mut writer: impl Write
     ) -> Result<(), Error>
     {
-        let request = PartialRequest::parse(
-            self.partial.take(),
-            &mut self.request_state,
-            request
-        )?;
+        let mut input = request;
 
-        if env::var("PRINT_REQUESTS").is_ok()
+        // a single read can have multiple pipelined requests in it
+        while let Some(request) = self.partial.parse(input)?
         {
-            eprintln!("{:#?}", &request.request);
+            input = &[];
+
+            self.handle(request, &mut writer)?;
+
+            if !self.alive
+            {
+                break;
+            }
         }
 
-        if request.is_partial
+        Ok(())
+    }
+
+    fn respond_http2(
+        &mut self,
+        connection: &mut http2::Connection,
+        input: &[u8],
+        mut writer: impl Write
+    ) -> Result<(), Error>
+    {
+        // streams get answered one after another but their bodies get sent interleaved
+        for (stream, request) in connection.receive(input, &mut writer)?
         {
-            self.partial = Some(request.request);
+            let response = request.map_err(Error::from).and_then(|request| self.response(request))
+                .unwrap_or_else(|err| self.status_response(err.status()));
 
-            return Ok(());
+            let response = if self.is_head { response.without_body() } else { response };
+            self.is_head = false;
+
+            connection.send_response(stream, response, &mut writer)?;
         }
 
-        self.partial = None;
-        let request = request.request;
+        connection.send_pending(&mut writer)?;
+
+        Ok(())
+    }
+
+    fn handle(&mut self, request: Request, writer: impl Write) -> Result<(), Error>
+    {
+        let response = self.response(request)?;
+
+        let result = self.send(writer, response);
+
+        self.is_head = false;
 
-        let request_header = &request.header;
-        match request_header.request
+        result
+    }
+
+    fn response(&mut self, mut request: Request) -> Result<Response, Error>
+    {
+        self.settings = Arc::clone(self.hosts.select(request.headers.host()));
+
+        let request_type = request.header.request;
+        self.is_head = request_type == RequestType::Head;
+
+        // dot segments get resolved first so /public/../admin cant sneak past an /admin rule
+        let Some(path) = request.header.target.normalized_path() else
+        {
+            return Err(Error::Forbidden);
+        };
+
+        let policy = self.settings.client_auth_policy(&path);
+        if policy != ClientAuthPolicy::None
+        {
+            request.client = self.client.clone();
+        }
+
+        if self.settings.log_requests
+        {
+            eprintln!("{:#?}", &request);
+        }
+
+        let response = if let Some(port) = self.https_redirect
+        {
+            self.https_response(&request, &path, port)
+        } else if policy == ClientAuthPolicy::Required && request.client.is_none()
         {
-            RequestType::Get =>
+            self.status_response(Status::Forbidden)
+        } else
+        {
+            // configured redirects apply before anything else no matter the method
+            match self.settings.redirect(&request.header.target)
             {
-                //dont open this to the internet lmao
-                let path = Self::relative_path(&request_header.body)?;
+                Some((status, location)) => Self::redirect_response(status, &location),
+                None => self.route(request)?
+            }
+        };
 
-                let path = if &request_header.body=="/"
-                {
-                    Path::new("index.html")
-                } else
-                {
-                    &path
-                };
+        Ok(response)
+    }
 
-                let response = if path.exists()
-                {
-                    match fs::read(path)
-                    {
-                        Err(_) => self.not_found(),
-                        Ok(bytes) =>
-                        {
-                            let content_type = Self::extension_content_type(path)?;
-
-                            http::response(Status::Ok, content_type, &bytes)
-                        }
-                    }
-                } else
+    fn route(&mut self, request: Request) -> Result<Response, Error>
+    {
+        let response = match request.header.request
+        {
+            RequestType::Get | RequestType::Head => self.static_file(&request)?,
+            RequestType::Post =>
+            {
+                // the page gets answered the same way a GET would be
+                match post::handle(&self.settings, &request)?
                 {
-                    self.not_found()
-                };
-
-                writer.write_all(&response)?;
+                    Some(response) => response,
+                    None => self.static_file(&request)?
+                }
             },
-            RequestType::Post =>
+            RequestType::Options =>
+            {
+                Response::new(Status::NoContent).header("Allow", Self::allowed_methods())
+            },
+            _ =>
+            {
+                self.status_response(Status::MethodNotAllowed)
+                    .header("Allow", Self::allowed_methods())
+            }
+        };
+
+        Ok(response)
+    }
+
+    fn static_file(&mut self, request: &Request) -> Result<Response, Error>
+    {
+        let target = &request.header.target;
+
+        let mut path = self.settings.resolve_path(target)?;
+        let mut is_listing = false;
+
+        if path.is_dir()
+        {
+            // relative links inside the directory only work with the slash
+            if !target.path.ends_with('/')
+            {
+                let path = target.normalized_path().ok_or(Error::Forbidden)?;
+                let location = format!(
+                    "{}/{}",
+                    http::percent_encode_path(path.trim_end_matches('/')),
+                    target.query_suffix()
+                );
+
+                return Ok(Self::redirect_response(Status::MovedPermanently, &location));
+            }
+
+            // the index can be a symlink too so it has to stay inside the root like anything else
+            let index = self.settings.confine(path.join("index.html"))?;
+
+            if index.is_file()
+            {
+                path = index;
+            } else if self.settings.directory_listing
             {
-                post::handle(writer, request)?;
+                is_listing = true;
             }
         }
 
+        if !path.is_file() && !is_listing
+        {
+            return Ok(self.status_response(Status::NotFound));
+        }
+
+        let response = if is_listing
+        {
+            files::listing(&path, request, &self.settings)
+        } else
+        {
+            files::respond(&path, request, &self.settings)
+        };
+
+        match response
+        {
+            Ok(response) =>
+            {
+                let cacheable = matches!(
+                    response.status(),
+                    Status::Ok | Status::PartialContent | Status::NotModified
+                );
+
+                let cache_control = target.normalized_path()
+                    .and_then(|path| self.settings.cache_control(&path))
+                    .filter(|_| cacheable);
+
+                Ok(match cache_control
+                {
+                    Some(value) => response.header("Cache-Control", value),
+                    None => response
+                })
+            },
+            Err(Error::WritingError(_)) => Ok(self.status_response(Status::NotFound)),
+            Err(err) => Err(err)
+        }
+    }
+
+    // path is the normalized one so the location gets built the same way as the slash redirect
+    fn https_response(&mut self, request: &Request, path: &str, port: u16) -> Response
+    {
+        let Some(host) = request.headers.host().filter(|host| !host.is_empty()) else
+        {
+            return self.status_response(Status::BadRequest);
+        };
+
+        let port = if port == 443 { String::new() } else { format!(":{port}") };
+
+        let location = format!(
+            "https://{host}{port}{}{}",
+            http::percent_encode_path(path),
+            request.header.target.query_suffix()
+        );
+
+        Self::redirect_response(Status::MovedPermanently, &location)
+    }
+
+    fn redirect_response(status: Status, location: &str) -> Response
+    {
+        Response::new(status)
+            .content_type(ContentType::Html)
+            .header("Location", location)
+            .body(format!("{} {}", status.code(), status.reason().to_lowercase()))
+    }
+
+    fn send(&self, mut writer: impl Write, mut response: Response) -> Result<(), Error>
+    {
+        if self.is_head
+        {
+            response = response.without_body();
+        }
+
+        if !self.alive
+        {
+            response = response.close();
+        }
+
+        response.write_to(&mut writer).map_err(Error::Aborted)?;
+
         Ok(())
     }
 
@@ -188,10 +747,587 @@ impl SmolServer
         self.alive
     }
 
-    fn not_found(&mut self) -> Vec<u8>
+    fn allowed_methods() -> String
+    {
+        ALLOWED_METHODS.iter().map(|x| x.as_str()).collect::<Vec<_>>().join(", ")
+    }
+
+    fn status_response(&mut self, status: Status) -> Response
     {
         self.alive = false;
 
-        http::response(Status::NotFound, ContentType::Html, b"404 not found")
+        Response::new(status)
+            .content_type(ContentType::Html)
+            .body(format!("{} {}", status.code(), status.reason().to_lowercase()))
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::fs;
+
+    use super::*;
+    use crate::test_dir::TestDir;
+
+
+    fn test_root(name: &str) -> (TestDir, Settings)
+    {
+        let base = TestDir::new(name);
+
+        let root = base.join("root");
+        fs::create_dir_all(root.join("sub")).unwrap();
+
+        fs::write(root.join("index.html"), b"index").unwrap();
+        fs::write(root.join("sub").join("file.txt"), b"file").unwrap();
+        fs::write(base.join("secret.txt"), b"secret").unwrap();
+
+        let settings = Settings::new(&root).unwrap();
+
+        (base, settings)
+    }
+
+    // everything written back for one raw request
+    fn answer(settings: &Settings, request: &str) -> String
+    {
+        let mut server = SmolServer::new(Arc::new(VirtualHosts::new(settings.clone())));
+
+        let mut output = Vec::new();
+        let _ = server.respond(request.as_bytes(), &mut output);
+
+        String::from_utf8_lossy(&output).into_owned()
+    }
+
+    fn get(settings: &Settings, target: &str) -> String
+    {
+        answer(settings, &format!("GET {target} HTTP/1.1\r\nHost: a\r\n\r\n"))
+    }
+
+    fn resolve(settings: &Settings, target: &str) -> Result<PathBuf, Error>
+    {
+        settings.resolve_path(&RequestTarget::parse(target).unwrap())
+    }
+
+    fn is_forbidden(result: Result<PathBuf, Error>) -> bool
+    {
+        matches!(result, Err(Error::Forbidden))
+    }
+
+    #[test]
+    fn resolves_inside_root()
+    {
+        let (_base, settings) = test_root("inside");
+
+        let path = resolve(&settings, "/sub/file.txt").unwrap();
+        assert_eq!(path, settings.root.join("sub").join("file.txt"));
+
+        let path = resolve(&settings, "/sub/../index.html").unwrap();
+        assert_eq!(path, settings.root.join("index.html"));
+
+        let path = resolve(&settings, "/sub/./file.txt").unwrap();
+        assert_eq!(path, settings.root.join("sub").join("file.txt"));
+
+        let path = resolve(&settings, "/index.html?v=3#top").unwrap();
+        assert_eq!(path, settings.root.join("index.html"));
+
+        let path = resolve(&settings, "/sub/fi%6Ce.txt").unwrap();
+        assert_eq!(path, settings.root.join("sub").join("file.txt"));
+
+        let path = resolve(&settings, "/missing.txt").unwrap();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn rejects_traversal()
+    {
+        let (_base, settings) = test_root("traversal");
+
+        assert!(is_forbidden(resolve(&settings, "/../secret.txt")));
+        assert!(is_forbidden(resolve(&settings, "/sub/../../secret.txt")));
+        assert!(is_forbidden(resolve(&settings, "/../root/../secret.txt")));
+        assert!(is_forbidden(resolve(&settings, "/../../../../etc/passwd")));
+    }
+
+    #[test]
+    fn rejects_encoded_traversal()
+    {
+        let (_base, settings) = test_root("encoded");
+
+        assert!(is_forbidden(resolve(&settings, "/%2e%2e/secret.txt")));
+        assert!(is_forbidden(resolve(&settings, "/%2E%2E/secret.txt")));
+        assert!(is_forbidden(resolve(&settings, "/sub/..%2f..%2fsecret.txt")));
+        assert!(is_forbidden(resolve(&settings, "/sub/..%5c..%5csecret.txt")));
+        assert!(is_forbidden(resolve(&settings, "/index.html%00.png")));
+
+        assert!(RequestTarget::parse("/%zz").is_err());
+    }
+
+    #[test]
+    fn matches_patterns()
+    {
+        assert!(pattern_matches("*.wasm", "/pkg/game.wasm"));
+        assert!(pattern_matches("/static/*", "/static/a/b.png"));
+        assert!(pattern_matches("/a/*/c*", "/a/b/cd"));
+        assert!(pattern_matches("/exact", "/exact"));
+        assert!(pattern_matches("*", "/anything"));
+
+        assert!(!pattern_matches("*.wasm", "/game.wasm.map"));
+        assert!(!pattern_matches("/static/*", "/other/static/a"));
+        assert!(!pattern_matches("/exact", "/exact/"));
+        assert!(!pattern_matches("/a*b*c", "/acb"));
+    }
+
+    #[test]
+    fn matches_hosts()
+    {
+        assert!(host_matches("example.com", "example.com"));
+        assert!(host_matches("example.com", "EXAMPLE.com."));
+        assert!(host_matches("*.example.com", "www.example.com"));
+
+        assert!(!host_matches("example.com", "www.example.com"));
+        assert!(!host_matches("*.example.com", "example.com"));
+        assert!(!host_matches("*.example.com", "a.b.example.com"));
+        assert!(!host_matches("*.example.com", "wwwexample.com"));
+
+        let (_base, settings) = test_root("hosts");
+
+        let mut hosts = VirtualHosts::new(settings.clone());
+        hosts.add(vec!["a.test".to_owned()], settings.with_root(settings.root.join("sub")).unwrap());
+
+        assert_eq!(hosts.select(Some("a.test")).root, settings.root.join("sub"));
+        assert_eq!(hosts.select(Some("b.test")).root, settings.root);
+        assert_eq!(hosts.select(None).root, settings.root);
+    }
+
+    #[test]
+    fn matches_redirects()
+    {
+        let (_base, mut settings) = test_root("redirects");
+
+        let rule = |from: &str, to: &str, status, prefix|
+        {
+            RedirectRule{from: from.to_owned(), to: to.to_owned(), status, prefix}
+        };
+
+        settings.redirects = vec![
+            rule("/old.html", "/new.html", 301, false),
+            rule("/docs", "/manual", 308, true),
+            rule("/away/", "https://example.com/", 302, true)
+        ];
+
+        let redirect = |target: &str|
+        {
+            settings.redirect(&RequestTarget::parse(target).unwrap())
+                .map(|(status, location)| (status.code(), location))
+        };
+
+        assert_eq!(redirect("/old.html"), Some((301, "/new.html".to_owned())));
+        assert_eq!(redirect("/old.html?a=b#c"), Some((301, "/new.html?a=b".to_owned())));
+        assert_eq!(redirect("/old.html/"), None);
+
+        assert_eq!(redirect("/docs"), Some((308, "/manual".to_owned())));
+        assert_eq!(redirect("/docs/a/b.html"), Some((308, "/manual/a/b.html".to_owned())));
+        assert_eq!(redirect("/docsfoo"), None);
+
+        assert_eq!(redirect("/away/x?q"), Some((302, "https://example.com/x?q".to_owned())));
+        assert_eq!(redirect("/away/a%20b"), Some((302, "https://example.com/a%20b".to_owned())));
+        assert_eq!(redirect("/x/../old.html"), Some((301, "/new.html".to_owned())));
+        assert_eq!(redirect("/./docs/a"), Some((308, "/manual/a".to_owned())));
+        assert_eq!(redirect("/index.html"), None);
+    }
+
+    #[test]
+    fn redirects_directories_to_the_slash()
+    {
+        let (_base, settings) = test_root("trailing_slash");
+
+        fs::create_dir_all(settings.root.join("a b").join("ünï")).unwrap();
+
+        let location = |target: &str|
+        {
+            let response = get(&settings, target);
+            assert!(response.starts_with("HTTP/1.1 301"), "{target}: {response}");
+
+            response.lines().find_map(|line| line.strip_prefix("Location: ")).map(str::to_owned)
+        };
+
+        assert_eq!(location("/sub").as_deref(), Some("/sub/"));
+        assert_eq!(location("/sub?a=b&c").as_deref(), Some("/sub/?a=b&c"));
+        assert_eq!(location("/sub#top").as_deref(), Some("/sub/"));
+        assert_eq!(location("/index.html/../sub").as_deref(), Some("/sub/"));
+        assert_eq!(location("/a%20b").as_deref(), Some("/a%20b/"));
+        assert_eq!(location("/a%20b/%C3%BCn%C3%AF").as_deref(), Some("/a%20b/%C3%BCn%C3%AF/"));
+
+        assert!(get(&settings, "/sub/").starts_with("HTTP/1.1 404"));
+        assert!(get(&settings, "/sub/file.txt").starts_with("HTTP/1.1 200"));
+    }
+
+    #[test]
+    fn redirects_to_https()
+    {
+        let (_base, settings) = test_root("https_redirect");
+        let hosts = Arc::new(VirtualHosts::new(settings));
+
+        let redirect = |port: u16, target: &str, host: Option<&str>|
+        {
+            let mut server = SmolServer::new(Arc::clone(&hosts)).redirecting_to_https(port);
+
+            let host = host.map(|host| format!("Host: {host}\r\n")).unwrap_or_default();
+            let request = format!("GET {target} HTTP/1.1\r\n{host}\r\n");
+
+            let mut output = Vec::new();
+            let _ = server.respond(request.as_bytes(), &mut output);
+
+            String::from_utf8_lossy(&output).into_owned()
+        };
+
+        let location = |port: u16, target: &str, host: &str|
+        {
+            let response = redirect(port, target, Some(host));
+            assert!(response.starts_with("HTTP/1.1 301"), "{target}: {response}");
+
+            response.lines().find_map(|line| line.strip_prefix("Location: ")).map(str::to_owned)
+        };
+
+        let https = |location: &str| Some(location.to_owned());
+
+        let file = https("https://a.test/sub/file.txt");
+        assert_eq!(location(443, "/sub/file.txt", "a.test:80"), file);
+        assert_eq!(location(8443, "/", "a.test"), https("https://a.test:8443/"));
+        assert_eq!(location(8443, "/", "[::1]:8080"), https("https://[::1]:8443/"));
+        assert_eq!(location(443, "/a?x=1&y#top", "a.test"), https("https://a.test/a?x=1&y"));
+
+        // the same path everything else matched against, not whatever the client sent
+        assert_eq!(location(443, "/x/../sub/./file.txt", "a.test"), file);
+        assert_eq!(location(443, "/%61%20b/", "a.test"), https("https://a.test/a%20b/"));
+        assert_eq!(location(443, "/%3Cx%3E", "a.test"), https("https://a.test/%3Cx%3E"));
+
+        assert!(redirect(443, "/", None).starts_with("HTTP/1.1 400"));
+        assert!(redirect(443, "/", Some("")).starts_with("HTTP/1.1 400"));
+        assert!(redirect(443, "/../secret.txt", Some("a.test")).starts_with("HTTP/1.1 403"));
+    }
+
+    #[test]
+    fn picks_client_auth_policies()
+    {
+        let (_base, mut settings) = test_root("client_auth");
+
+        let rule = |pattern: &str, policy|
+        {
+            ClientAuthRule{pattern: pattern.to_owned(), policy}
+        };
+
+        settings.client_auth = vec![
+            rule("/admin/public/*", ClientAuthPolicy::None),
+            rule("/admin/*", ClientAuthPolicy::Required)
+        ];
+
+        assert_eq!(settings.client_auth_policy("/admin/a.html"), ClientAuthPolicy::Required);
+        assert_eq!(settings.client_auth_policy("/admin/public/a.html"), ClientAuthPolicy::None);
+        assert_eq!(settings.client_auth_policy("/index.html"), ClientAuthPolicy::Optional);
+    }
+
+    #[test]
+    fn resolves_dot_segments_before_client_auth()
+    {
+        let (_base, mut settings) = test_root("dot_segments");
+
+        fs::create_dir_all(settings.root.join("admin")).unwrap();
+        fs::write(settings.root.join("admin").join("secret.txt"), b"admin secret").unwrap();
+
+        settings.client_auth = vec![
+            ClientAuthRule{pattern: "/admin/*".to_owned(), policy: ClientAuthPolicy::Required}
+        ];
+
+        let hosts = Arc::new(VirtualHosts::new(settings));
+
+        let get = |target: &str|
+        {
+            let mut server = SmolServer::new(Arc::clone(&hosts));
+
+            let request = format!("GET {target} HTTP/1.1\r\nHost: a\r\n\r\n");
+
+            let mut output = Vec::new();
+            let _ = server.respond(request.as_bytes(), &mut output);
+
+            String::from_utf8_lossy(&output).into_owned()
+        };
+
+        for target in [
+            "/admin/secret.txt",
+            "/public/../admin/secret.txt",
+            "/./admin/secret.txt",
+            "/sub/%2e%2e/admin/secret.txt",
+            "/admin/./secret.txt"
+        ]
+        {
+            let response = get(target);
+
+            assert!(response.starts_with("HTTP/1.1 403"), "{target}: {response}");
+            assert!(!response.contains("admin secret"), "{target}: {response}");
+        }
+
+        assert!(get("/../admin/secret.txt").starts_with("HTTP/1.1 403"));
+        assert!(get("/sub/../index.html").starts_with("HTTP/1.1 200"));
+    }
+
+    #[test]
+    fn rejects_malformed_parts()
+    {
+        let (_base, settings) = test_root("malformed_parts");
+
+        let hosts = Arc::new(VirtualHosts::new(settings));
+
+        let post = |part_headers: &str|
+        {
+            let body = format!("--b\r\n{part_headers}\r\n\r\ndata\r\n--b--\r\n");
+            let request = format!(
+                "POST /index.html HTTP/1.1\r\nHost: a\r\n\
+                Content-Type: multipart/form-data; boundary=b\r\nContent-Length: {}\r\n\r\n{body}",
+                body.len()
+            );
+
+            let mut output = Vec::new();
+            let _ = SmolServer::new(Arc::clone(&hosts)).respond(request.as_bytes(), &mut output);
+
+            String::from_utf8_lossy(&output).into_owned()
+        };
+
+        assert!(post("Content-Type: text/plain").starts_with("HTTP/1.1 400"));
+        assert!(post("Content-Disposition: form-data").starts_with("HTTP/1.1 400"));
+        assert!(post("Content-Disposition: form-data; name=\"f\"").starts_with("HTTP/1.1 400"));
+    }
+
+    #[test]
+    fn answers_posts_with_the_page()
+    {
+        let (_base, settings) = test_root("post_page");
+
+        let hosts = Arc::new(VirtualHosts::new(settings));
+
+        let post = |target: &str|
+        {
+            let request = format!(
+                "POST {target} HTTP/1.1\r\nHost: a\r\nContent-Type: text/plain\r\n\
+                Content-Length: 2\r\n\r\nhi"
+            );
+
+            let mut output = Vec::new();
+            SmolServer::new(Arc::clone(&hosts)).respond(request.as_bytes(), &mut output).unwrap();
+
+            String::from_utf8_lossy(&output).into_owned()
+        };
+
+        let response = post("/");
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        assert!(response.ends_with("\r\n\r\nindex"), "{response}");
+        assert_eq!(response.matches("HTTP/1.1").count(), 1);
+
+        assert!(post("/sub/").starts_with("HTTP/1.1 404"));
+        assert!(post("/sub/file.txt").ends_with("\r\n\r\nfile"));
+    }
+
+    #[test]
+    fn answers_other_methods()
+    {
+        let (_base, settings) = test_root("methods");
+
+        // the date could tick over between two requests
+        let request = |method: &str, target: &str|
+        {
+            answer(&settings, &format!("{method} {target} HTTP/1.1\r\nHost: a\r\n\r\n"))
+                .split_inclusive("\r\n")
+                .filter(|line| !line.starts_with("Date: "))
+                .collect::<String>()
+        };
+
+        // the same head a get would get, just without the body
+        let get = request("GET", "/sub/file.txt");
+        let head = request("HEAD", "/sub/file.txt");
+        assert!(head.starts_with("HTTP/1.1 200"), "{head}");
+        assert!(head.contains("Content-Length: 4\r\n"), "{head}");
+        assert!(head.ends_with("\r\n\r\n"), "{head}");
+        assert_eq!(get.strip_suffix("file"), Some(head.as_str()));
+
+        let head = request("HEAD", "/missing.txt");
+        assert!(head.starts_with("HTTP/1.1 404"), "{head}");
+        assert!(head.ends_with("\r\n\r\n"), "{head}");
+
+        let options = request("OPTIONS", "/index.html");
+        assert!(options.starts_with("HTTP/1.1 204"), "{options}");
+        assert!(options.contains("Allow: GET, HEAD, POST, OPTIONS\r\n"), "{options}");
+        assert!(options.ends_with("\r\n\r\n"), "{options}");
+
+        for method in ["PUT", "DELETE", "PATCH"]
+        {
+            let response = request(method, "/index.html");
+            assert!(response.starts_with("HTTP/1.1 405"), "{method}: {response}");
+            assert!(response.contains("Allow: GET, HEAD, POST, OPTIONS\r\n"), "{response}");
+            assert!(response.contains("Connection: close\r\n"), "{response}");
+        }
+
+        assert_eq!(fs::read(settings.root.join("index.html")).unwrap(), b"index");
+    }
+
+    #[test]
+    fn never_answers_twice()
+    {
+        // lets the head through and then fails once, like a body that couldnt be read
+        struct FailingBody
+        {
+            output: Vec<u8>,
+            writes: usize
+        }
+
+        impl Write for FailingBody
+        {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize>
+            {
+                self.writes += 1;
+
+                if self.writes == 2
+                {
+                    return Err(io::Error::other("body failed"));
+                }
+
+                self.output.write(buf)
+            }
+
+            fn flush(&mut self) -> io::Result<()>
+            {
+                Ok(())
+            }
+        }
+
+        let (_base, settings) = test_root("answers_twice");
+
+        let mut server = SmolServer::new(Arc::new(VirtualHosts::new(settings)));
+
+        let mut writer = FailingBody{output: Vec::new(), writes: 0};
+        let result = server.respond(b"GET /index.html HTTP/1.1\r\nHost: a\r\n\r\n", &mut writer);
+
+        assert!(matches!(result, Err(Error::Aborted(_))));
+        assert!(!server.alive());
+
+        let output = String::from_utf8_lossy(&writer.output);
+        assert!(output.starts_with("HTTP/1.1 200"));
+        assert_eq!(output.matches("HTTP/1.1").count(), 1, "{output}");
+    }
+
+    #[test]
+    fn serves_http2()
+    {
+        let (_base, settings) = test_root("http2");
+
+        let mut server = SmolServer::new(Arc::new(VirtualHosts::new(settings)));
+        server.use_http2(false);
+
+        let frame = |kind: u8, flags: u8, stream: u32, payload: &[u8]|
+        {
+            let mut frame = (payload.len() as u32).to_be_bytes()[1..].to_vec();
+            frame.extend([kind, flags]);
+            frame.extend(stream.to_be_bytes());
+            frame.extend(payload);
+
+            frame
+        };
+
+        let fields = [(":method", "GET"), (":scheme", "https"), (":path", "/"), (":authority", "a")]
+            .into_iter()
+            .map(|(name, value)| (name.to_owned(), value.to_owned()))
+            .collect::<Vec<_>>();
+
+        let mut input = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n".to_vec();
+        input.extend(frame(0x4, 0, 0, &[]));
+        // end stream and end headers
+        input.extend(frame(0x1, 0x5, 1, &hpack::encode(&fields)));
+
+        let mut output = Vec::new();
+        server.respond(&input, &mut output).unwrap();
+
+        let mut frames = Vec::new();
+        let mut rest = &output[..];
+        while rest.len() >= 9
+        {
+            let length = u32::from_be_bytes([0, rest[0], rest[1], rest[2]]) as usize;
+            let stream = u32::from_be_bytes([rest[5], rest[6], rest[7], rest[8]]);
+
+            frames.push((rest[3], rest[4], stream, rest[9..9 + length].to_vec()));
+            rest = &rest[9 + length..];
+        }
+
+        assert!(rest.is_empty());
+
+        // settings, settings ack, then the response
+        assert_eq!((frames[0].0, frames[0].1), (0x4, 0));
+        assert_eq!((frames[1].0, frames[1].1), (0x4, 0x1));
+
+        let headers = hpack::Decoder::new().decode(&frames[2].3).unwrap();
+        assert_eq!(frames[2].2, 1);
+        assert!(headers.contains(&(b":status".to_vec(), b"200".to_vec())));
+        assert!(headers.contains(&(b"content-length".to_vec(), b"5".to_vec())));
+
+        assert_eq!((frames[3].0, frames[3].1, frames[3].2), (0x0, 0x1, 1));
+        assert_eq!(frames[3].3, b"index");
+
+        assert!(server.alive());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rejects_symlink_escape()
+    {
+        let (base, settings) = test_root("symlink");
+
+        std::os::unix::fs::symlink(base.join("secret.txt"), settings.root.join("link.txt"))
+            .unwrap();
+
+        std::os::unix::fs::symlink(&base, settings.root.join("up")).unwrap();
+
+        assert!(is_forbidden(resolve(&settings, "/link.txt")));
+        assert!(is_forbidden(resolve(&settings, "/up/secret.txt")));
+    }
+
+    #[test]
+    fn prefers_index_files_over_listings()
+    {
+        let (_base, mut settings) = test_root("index_listing");
+        settings.directory_listing = true;
+
+        let response = get(&settings, "/");
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        assert!(response.ends_with("\r\n\r\nindex"), "{response}");
+
+        let response = get(&settings, "/sub/");
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        assert!(response.contains("<a href=\"/sub/file.txt\">file.txt</a>"), "{response}");
+
+        fs::write(settings.root.join("sub").join("index.html"), b"sub index").unwrap();
+        assert!(get(&settings, "/sub/").ends_with("\r\n\r\nsub index"));
+
+        settings.directory_listing = false;
+        fs::create_dir_all(settings.root.join("empty")).unwrap();
+        assert!(get(&settings, "/empty/").starts_with("HTTP/1.1 404"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rejects_symlinked_index_escape()
+    {
+        let (base, settings) = test_root("symlink_index");
+
+        let index = settings.root.join("sub").join("index.html");
+        std::os::unix::fs::symlink(base.join("secret.txt"), &index).unwrap();
+
+        let response = get(&settings, "/sub/");
+        assert!(response.starts_with("HTTP/1.1 403"), "{response}");
+        assert!(!response.contains("secret"), "{response}");
+
+        // links that stay inside the root are fine
+        fs::remove_file(&index).unwrap();
+        std::os::unix::fs::symlink(settings.root.join("index.html"), &index).unwrap();
+
+        let response = get(&settings, "/sub/");
+        assert!(response.ends_with("\r\n\r\nindex"), "{response}");
     }
 }
@@ -0,0 +1,265 @@
+use std::{
+    io::{Read, BufReader},
+    path::{Path, PathBuf}
+};
+
+use serde::Deserialize;
+
+use super::{
+    http::Headers,
+    Settings,
+    ContentType
+};
+
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CompressionSettings
+{
+    pub enabled: bool,
+    // anything smaller isnt worth the cpu time
+    pub min_size: u64,
+    pub gzip_level: u32,
+    pub brotli_level: u32,
+    pub zstd_level: i32
+}
+
+impl Default for CompressionSettings
+{
+    fn default() -> Self
+    {
+        Self{
+            enabled: true,
+            min_size: 1024,
+            gzip_level: 6,
+            brotli_level: 5,
+            zstd_level: 3
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding
+{
+    Brotli,
+    Zstd,
+    Gzip
+}
+
+impl Encoding
+{
+    // in the order the server prefers them
+    const ALL: [Encoding; 3] = [Encoding::Brotli, Encoding::Zstd, Encoding::Gzip];
+
+    pub fn as_str(&self) -> &'static str
+    {
+        match self
+        {
+            Encoding::Brotli => "br",
+            Encoding::Zstd => "zstd",
+            Encoding::Gzip => "gzip"
+        }
+    }
+
+    // the extension of a precompressed file next to the original
+    pub fn extension(&self) -> Option<&'static str>
+    {
+        match self
+        {
+            Encoding::Brotli => Some("br"),
+            Encoding::Gzip => Some("gz"),
+            Encoding::Zstd => None
+        }
+    }
+
+    pub fn encoder(
+        &self,
+        settings: &CompressionSettings,
+        reader: impl Read + Send + 'static
+    ) -> Box<dyn Read + Send>
+    {
+        match self
+        {
+            Encoding::Brotli =>
+            {
+                Box::new(brotli::CompressorReader::new(reader, 4096, settings.brotli_level, 22))
+            },
+            Encoding::Zstd =>
+            {
+                let encoder = zstd::stream::read::Encoder::with_buffer(
+                    BufReader::new(reader),
+                    settings.zstd_level
+                ).expect("config only allows valid zstd levels");
+
+                Box::new(encoder)
+            },
+            Encoding::Gzip =>
+            {
+                let level = flate2::Compression::new(settings.gzip_level.min(9));
+
+                Box::new(flate2::read::GzEncoder::new(reader, level))
+            }
+        }
+    }
+}
+
+pub fn is_compressible(content_type: ContentType) -> bool
+{
+    matches!(
+        content_type,
+        ContentType::Html
+            | ContentType::Javascript
+            | ContentType::Css
+            | ContentType::Txt
+            | ContentType::Json
+            | ContentType::Wasm
+            | ContentType::Icon
+            | ContentType::Ttf
+    )
+}
+
+// picks the encodings the client accepts, best first
+pub fn accepted(headers: &Headers) -> Vec<Encoding>
+{
+    let weights = headers.values("Accept-Encoding").filter_map(|value|
+    {
+        let mut parts = value.split(';');
+
+        let name = parts.next()?.trim().to_lowercase();
+
+        let weight = parts.find_map(|parameter|
+        {
+            let (key, value) = parameter.split_once('=')?;
+
+            key.trim().eq_ignore_ascii_case("q").then_some(value)
+        }).map_or(Some(1.0), |value| value.trim().parse::<f32>().ok())?;
+
+        Some((name, weight))
+    }).collect::<Vec<_>>();
+
+    let weight_of = |names: &[&str]|
+    {
+        names.iter().find_map(|name| weights.iter().find(|(x, _)| x == name))
+            .or_else(|| weights.iter().find(|(x, _)| x == "*"))
+            .map(|(_, weight)| *weight)
+    };
+
+    let mut accepted = Encoding::ALL.into_iter().filter_map(|encoding|
+    {
+        let weight = match encoding
+        {
+            // x-gzip is the same thing
+            Encoding::Gzip => weight_of(&["gzip", "x-gzip"]),
+            encoding => weight_of(&[encoding.as_str()])
+        }?;
+
+        (weight > 0.0).then_some((encoding, weight))
+    }).collect::<Vec<_>>();
+
+    // stable sort keeps the server preference for equal weights
+    accepted.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+
+    accepted.into_iter().map(|(encoding, _)| encoding).collect()
+}
+
+// a precompressed sibling (like index.html.br) if the client takes that encoding,
+// the sibling has to stay inside the root just like the file itself
+pub fn precompressed(
+    settings: &Settings,
+    path: &Path,
+    accepted: &[Encoding]
+) -> Option<(Encoding, PathBuf)>
+{
+    accepted.iter().find_map(|encoding|
+    {
+        let mut sibling = path.as_os_str().to_owned();
+        sibling.push(".");
+        sibling.push(encoding.extension()?);
+
+        let sibling = settings.confine(PathBuf::from(sibling)).ok()?;
+
+        sibling.is_file().then_some((*encoding, sibling))
+    })
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::fs;
+
+    use super::{*, super::Request};
+    use crate::test_dir::TestDir;
+
+
+    fn accepted_for(value: &str) -> Vec<Encoding>
+    {
+        let fields = [("accept-encoding".to_owned(), value.to_owned())];
+
+        accepted(&Request::from_parts("GET", "/", &fields, Vec::new(), &[]).unwrap().headers)
+    }
+
+    #[test]
+    fn picks_accepted_encodings()
+    {
+        assert_eq!(accepted_for("gzip"), [Encoding::Gzip]);
+        assert_eq!(accepted_for("x-gzip, deflate"), [Encoding::Gzip]);
+        assert_eq!(
+            accepted_for("gzip;q=0.5, zstd;q=0.8, br"),
+            [Encoding::Brotli, Encoding::Zstd, Encoding::Gzip]
+        );
+        assert_eq!(accepted_for("GZIP; Q=0.1"), [Encoding::Gzip]);
+        assert_eq!(accepted_for("*;q=0.5, br;q=0"), [Encoding::Zstd, Encoding::Gzip]);
+        assert_eq!(accepted_for("gzip;q=0, identity"), []);
+        assert_eq!(accepted_for("gzip;q=nope, br"), [Encoding::Brotli]);
+        assert_eq!(accepted_for(""), []);
+    }
+
+    #[test]
+    fn compresses_known_text_only()
+    {
+        for extension in ["html", "js", "css", "txt", "json", "wasm"]
+        {
+            assert!(is_compressible(ContentType::create(extension)), "{extension}");
+        }
+
+        for extension in ["png", "jpg", "webp", "opus", "mp3", "woff", "mp4", "zip", "pdf", "gz"]
+        {
+            assert!(!is_compressible(ContentType::create(extension)), "{extension}");
+        }
+    }
+
+    #[test]
+    fn finds_precompressed_siblings()
+    {
+        let base = TestDir::new("precompressed");
+
+        let root = base.join("root");
+        fs::create_dir_all(&root).unwrap();
+
+        fs::write(root.join("a.js"), b"a").unwrap();
+        fs::write(root.join("a.js.br"), b"br").unwrap();
+        fs::write(root.join("a.js.gz"), b"gz").unwrap();
+        fs::write(base.join("secret"), b"secret").unwrap();
+
+        let settings = Settings::new(&root).unwrap();
+        let path = settings.root.join("a.js");
+
+        let found = precompressed(&settings, &path, &[Encoding::Gzip, Encoding::Brotli]);
+        assert_eq!(found, Some((Encoding::Gzip, settings.root.join("a.js.gz"))));
+
+        let found = precompressed(&settings, &path, &[Encoding::Zstd, Encoding::Brotli]);
+        assert_eq!(found, Some((Encoding::Brotli, settings.root.join("a.js.br"))));
+
+        assert_eq!(precompressed(&settings, &path, &[Encoding::Zstd]), None);
+        assert_eq!(precompressed(&settings, &path, &[]), None);
+
+        #[cfg(unix)]
+        {
+            fs::write(root.join("b.js"), b"b").unwrap();
+            std::os::unix::fs::symlink(base.join("secret"), root.join("b.js.gz")).unwrap();
+
+            let path = settings.root.join("b.js");
+            assert_eq!(precompressed(&settings, &path, &[Encoding::Gzip]), None);
+        }
+    }
+}
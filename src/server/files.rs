@@ -0,0 +1,871 @@
+use std::{
+    io::{self, Read, Seek, SeekFrom, Cursor},
+    fs::{self, File},
+    cmp::Ordering,
+    path::Path,
+    collections::VecDeque,
+    time::{SystemTime, UNIX_EPOCH}
+};
+
+use super::{
+    http::{self, Headers, ByteRange},
+    compression::{self, Encoding},
+    SmolServer,
+    Settings,
+    Error,
+    Status,
+    ContentType,
+    Request,
+    Response
+};
+
+
+const RANGES_BOUNDARY: &str = "FUNSERVERBYTERANGES";
+
+enum RangePiece
+{
+    Bytes(Cursor<Vec<u8>>),
+    File{start: u64, left: u64}
+}
+
+// reads the parts of a multipart/byteranges body one after another
+struct RangesReader
+{
+    file: File,
+    pieces: VecDeque<RangePiece>
+}
+
+impl Read for RangesReader
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>
+    {
+        while let Some(piece) = self.pieces.front_mut()
+        {
+            let amount = match piece
+            {
+                RangePiece::Bytes(bytes) => bytes.read(buf)?,
+                RangePiece::File{start, left} =>
+                {
+                    let wanted = (*left).min(buf.len() as u64) as usize;
+
+                    self.file.seek(SeekFrom::Start(*start))?;
+                    let amount = self.file.read(&mut buf[..wanted])?;
+
+                    *start += amount as u64;
+                    *left -= amount as u64;
+
+                    amount
+                }
+            };
+
+            if amount != 0
+            {
+                return Ok(amount);
+            }
+
+            self.pieces.pop_front();
+        }
+
+        Ok(0)
+    }
+}
+
+pub fn respond(path: &Path, request: &Request, settings: &Settings) -> Result<Response, Error>
+{
+    let headers = &request.headers;
+    let compression = &settings.compression;
+
+    let content_type = SmolServer::extension_content_type(path)?;
+
+    let accepted = if compression.enabled { compression::accepted(headers) } else { Vec::new() };
+    let precompressed = compression::precompressed(settings, path, &accepted);
+
+    let (path, precompressed_encoding) = match &precompressed
+    {
+        Some((encoding, sibling)) => (sibling.as_path(), Some(*encoding)),
+        None => (path, None)
+    };
+
+    let mut file = File::open(path)?;
+    let metadata = file.metadata()?;
+
+    let length = metadata.len();
+
+    let compressible = compression.enabled && compression::is_compressible(content_type);
+
+    // http/1.0 clients cant read the chunked body a compressed stream gets sent as
+    let is_http10 = request.header.version_major == 1 && request.header.version_minor == 0;
+
+    // ranges of a compressed stream would need the whole thing compressed first
+    let encoding = precompressed_encoding.or_else(||
+    {
+        let worth_it = compressible
+            && length >= compression.min_size
+            && !headers.contains("Range")
+            && !is_http10;
+
+        worth_it.then(|| accepted.first().copied()).flatten()
+    });
+
+    let on_the_fly = encoding.is_some() && precompressed_encoding.is_none();
+
+    let modified = metadata.modified().ok();
+    let validators = Validators{
+        etag: modified.map(|modified| entity_tag(length, modified, encoding)),
+        last_modified: modified.map(http::http_date),
+        vary: compressible || precompressed_encoding.is_some()
+    };
+
+    if is_not_modified(headers, &validators, modified)
+    {
+        return Ok(validators.apply(Response::new(Status::NotModified)));
+    }
+
+    let ranges = headers.value("Range").filter(|_|
+    {
+        if_range_matches(headers, &validators)
+    }).and_then(|value| http::parse_ranges(value, length));
+
+    let response = match ranges
+    {
+        None if on_the_fly =>
+        {
+            let encoding = encoding.expect("on the fly always has an encoding");
+
+            Response::new(Status::Ok)
+                .content_type(content_type)
+                .stream(encoding.encoder(compression, file))
+        },
+        None => Response::new(Status::Ok).content_type(content_type).length(length).stream(file),
+        Some(ranges) if ranges.is_empty() =>
+        {
+            let status = Status::RangeNotSatisfiable;
+
+            Response::new(status)
+                .content_type(ContentType::Html)
+                .header("Content-Range", format!("bytes */{length}"))
+                .body(format!("{} {}", status.code(), status.reason().to_lowercase()))
+        },
+        Some(ranges) if ranges.len() == 1 =>
+        {
+            let range = ranges[0];
+
+            file.seek(SeekFrom::Start(range.start))?;
+
+            Response::new(Status::PartialContent)
+                .content_type(content_type)
+                .header("Content-Range", range.content_range(length))
+                .length(range.length())
+                .stream(file)
+        },
+        Some(ranges) => multiple_ranges(file, content_type, length, &ranges)
+    };
+
+    let response = match encoding
+    {
+        Some(encoding) => response.header("Content-Encoding", encoding.as_str()),
+        None => response
+    };
+
+    // ranges always count plain file bytes, which a compressed stream doesnt have
+    let response = if on_the_fly { response } else { response.header("Accept-Ranges", "bytes") };
+
+    Ok(validators.apply(response))
+}
+
+fn multiple_ranges(
+    file: File,
+    content_type: ContentType,
+    length: u64,
+    ranges: &[ByteRange]
+) -> Response
+{
+    let mut pieces = VecDeque::new();
+
+    ranges.iter().for_each(|range|
+    {
+        let part_header = format!(
+            "\r\n--{RANGES_BOUNDARY}\r\nContent-Type: {}\r\nContent-Range: {}\r\n\r\n",
+            content_type.as_str(),
+            range.content_range(length)
+        );
+
+        pieces.push_back(RangePiece::Bytes(Cursor::new(part_header.into_bytes())));
+        pieces.push_back(RangePiece::File{start: range.start, left: range.length()});
+    });
+
+    let end = format!("\r\n--{RANGES_BOUNDARY}--\r\n");
+    pieces.push_back(RangePiece::Bytes(Cursor::new(end.into_bytes())));
+
+    let body_length = pieces.iter().map(|piece|
+    {
+        match piece
+        {
+            RangePiece::Bytes(bytes) => bytes.get_ref().len() as u64,
+            RangePiece::File{left, ..} => *left
+        }
+    }).sum();
+
+    Response::new(Status::PartialContent)
+        .header("Content-Type", format!("multipart/byteranges; boundary={RANGES_BOUNDARY}"))
+        .length(body_length)
+        .stream(RangesReader{file, pieces})
+}
+
+// ranges only apply if the client still has the same version of the file
+fn if_range_matches(headers: &Headers, validators: &Validators) -> bool
+{
+    match headers.value("If-Range")
+    {
+        None => true,
+        // weak tags never match here
+        Some(value) if value.starts_with("W/") => false,
+        Some(value) if value.starts_with('"') => validators.etag.as_deref() == Some(value),
+        Some(value) => validators.last_modified.as_deref() == Some(value)
+    }
+}
+
+fn is_not_modified(
+    headers: &Headers,
+    validators: &Validators,
+    modified: Option<SystemTime>
+) -> bool
+{
+    // if-modified-since gets ignored when there are entity tags to compare
+    if headers.contains("If-None-Match")
+    {
+        let Some(etag) = validators.etag.as_deref() else { return false };
+
+        let strip_weak = |tag: &str| tag.strip_prefix("W/").unwrap_or(tag).to_owned();
+
+        return headers.values("If-None-Match").any(|tag|
+        {
+            tag == "*" || strip_weak(tag) == strip_weak(etag)
+        });
+    }
+
+    let since = headers.value("If-Modified-Since").and_then(http::parse_http_date);
+
+    match (since, modified)
+    {
+        (Some(since), Some(modified)) =>
+        {
+            // http dates only have second precision
+            let seconds = |time: SystemTime|
+            {
+                time.duration_since(UNIX_EPOCH).map(|x| x.as_secs()).unwrap_or(0)
+            };
+
+            seconds(modified) <= seconds(since)
+        },
+        _ => false
+    }
+}
+
+// each encoding is a different representation so it needs its own tag
+fn entity_tag(length: u64, modified: SystemTime, encoding: Option<Encoding>) -> String
+{
+    let modified = modified.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+    let suffix = encoding.map(|encoding| format!("-{}", encoding.as_str())).unwrap_or_default();
+
+    format!(
+        "\"{length:x}-{:x}-{:x}{suffix}\"",
+        modified.as_secs(),
+        modified.subsec_nanos()
+    )
+}
+
+struct Validators
+{
+    etag: Option<String>,
+    last_modified: Option<String>,
+    vary: bool
+}
+
+impl Validators
+{
+    fn apply(&self, mut response: Response) -> Response
+    {
+        if let Some(etag) = &self.etag
+        {
+            response = response.header("ETag", etag);
+        }
+
+        if let Some(last_modified) = &self.last_modified
+        {
+            response = response.header("Last-Modified", last_modified);
+        }
+
+        if self.vary
+        {
+            response = response.header("Vary", "Accept-Encoding");
+        }
+
+        response
+    }
+}
+
+struct Entry
+{
+    name: String,
+    is_directory: bool,
+    size: u64,
+    modified: Option<SystemTime>
+}
+
+impl Entry
+{
+    fn modified_seconds(&self) -> Option<u64>
+    {
+        self.modified.and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|modified| modified.as_secs())
+    }
+}
+
+pub fn listing(directory: &Path, request: &Request, settings: &Settings) -> Result<Response, Error>
+{
+    let target = &request.header.target;
+
+    let mut entries = fs::read_dir(directory)?.filter_map(|entry|
+    {
+        let entry = entry.ok()?;
+        let name = entry.file_name().into_string().ok()?;
+
+        // hidden files stay hidden
+        if name.starts_with('.')
+        {
+            return None;
+        }
+
+        let mut metadata = entry.metadata().ok()?;
+
+        // links only get followed while they stay inside the root, the rest dont get listed
+        if metadata.is_symlink()
+        {
+            metadata = fs::metadata(settings.confine(entry.path()).ok()?).ok()?;
+        }
+
+        Some(Entry{
+            name,
+            is_directory: metadata.is_dir(),
+            size: metadata.len(),
+            modified: metadata.modified().ok()
+        })
+    }).collect::<Vec<_>>();
+
+    let sort = target.query_value("sort").unwrap_or("name");
+    let descending = target.query_value("order") == Some("desc");
+
+    entries.sort_by(|a, b|
+    {
+        let ordering = match sort
+        {
+            "size" => a.size.cmp(&b.size),
+            "modified" => a.modified.cmp(&b.modified),
+            _ => Ordering::Equal
+        }.then_with(|| a.name.cmp(&b.name));
+
+        let ordering = if descending { ordering.reverse() } else { ordering };
+
+        // directories always go first
+        b.is_directory.cmp(&a.is_directory).then(ordering)
+    });
+
+    let wants_json = target.query_value("format") == Some("json")
+        || request.headers.values("Accept").next()
+            .is_some_and(|accept| accept.starts_with("application/json"));
+
+    let response = if wants_json
+    {
+        Response::new(Status::Ok)
+            .content_type(ContentType::Json)
+            .body(listing_json(&entries))
+    } else
+    {
+        Response::new(Status::Ok)
+            .content_type(ContentType::Html)
+            .body(listing_html(target, &entries, sort, descending))
+    };
+
+    Ok(response.header("Vary", "Accept"))
+}
+
+fn listing_json(entries: &[Entry]) -> String
+{
+    let entries = entries.iter().map(|entry|
+    {
+        let modified = entry.modified_seconds()
+            .map_or_else(|| "null".to_owned(), |modified| modified.to_string());
+
+        format!(
+            "{{\"name\":\"{}\",\"type\":\"{}\",\"size\":{},\"modified\":{modified}}}",
+            json_escape(&entry.name),
+            if entry.is_directory { "directory" } else { "file" },
+            entry.size
+        )
+    }).collect::<Vec<_>>();
+
+    format!("[{}]", entries.join(","))
+}
+
+fn listing_html(
+    target: &http::RequestTarget,
+    entries: &[Entry],
+    sort: &str,
+    descending: bool
+) -> String
+{
+    let base = target.segments.iter().fold(String::from("/"), |acc, segment|
+    {
+        acc + &http::percent_encode(segment) + "/"
+    });
+
+    let title = html_escape(&target.decoded_path());
+
+    let sort_link = |name: &str|
+    {
+        let order = if sort == name && !descending { "desc" } else { "asc" };
+
+        format!("<th><a href=\"?sort={name}&amp;order={order}\">{name}</a></th>")
+    };
+
+    let mut rows = Vec::new();
+
+    if !target.segments.is_empty()
+    {
+        rows.push("<tr><td><a href=\"../\">../</a></td><td></td><td></td></tr>".to_owned());
+    }
+
+    rows.extend(entries.iter().map(|entry|
+    {
+        let slash = if entry.is_directory { "/" } else { "" };
+
+        let href = format!("{base}{}{slash}", http::percent_encode(&entry.name));
+        let size = if entry.is_directory { String::new() } else { entry.size.to_string() };
+        let modified = entry.modified.map(http::http_date).unwrap_or_default();
+
+        format!(
+            "<tr><td><a href=\"{}\">{}{slash}</a></td><td>{size}</td><td>{modified}</td></tr>",
+            html_escape(&href),
+            html_escape(&entry.name)
+        )
+    }));
+
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>index of {title}</title></head>\n\
+        <body>\n<h1>index of {title}</h1>\n<table>\n<tr>{}{}{}</tr>\n{}\n</table>\n</body>\n</html>\n",
+        sort_link("name"),
+        sort_link("size"),
+        sort_link("modified"),
+        rows.join("\n")
+    )
+}
+
+fn html_escape(s: &str) -> String
+{
+    s.chars().map(|c|
+    {
+        match c
+        {
+            '&' => "&amp;".to_owned(),
+            '<' => "&lt;".to_owned(),
+            '>' => "&gt;".to_owned(),
+            '"' => "&quot;".to_owned(),
+            '\'' => "&#39;".to_owned(),
+            c => c.to_string()
+        }
+    }).collect()
+}
+
+fn json_escape(s: &str) -> String
+{
+    s.chars().map(|c|
+    {
+        match c
+        {
+            '"' => "\\\"".to_owned(),
+            '\\' => "\\\\".to_owned(),
+            c if (c as u32) < 0x20 => format!("\\u{:04x}", c as u32),
+            c => c.to_string()
+        }
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::{*, super::PartialRequest};
+    use crate::test_dir::TestDir;
+
+
+    fn test_file(name: &str, contents: &[u8]) -> (TestDir, Settings)
+    {
+        let base = TestDir::new(&format!("files_{name}"));
+        fs::create_dir_all(base.join("root")).unwrap();
+
+        fs::write(base.join("root").join("file.txt"), contents).unwrap();
+
+        let mut settings = Settings::new(base.join("root")).unwrap();
+        settings.compression.enabled = false;
+
+        (base, settings)
+    }
+
+    fn request(version: &str, fields: &[(&str, &str)]) -> Request
+    {
+        let fields = fields.iter().map(|(name, value)| format!("{name}: {value}\r\n"))
+            .collect::<String>();
+
+        let input = format!("GET / HTTP/{version}\r\n{fields}\r\n");
+
+        PartialRequest::new().parse(input.as_bytes()).unwrap().unwrap()
+    }
+
+    fn get(settings: &Settings, fields: &[(&str, &str)]) -> Response
+    {
+        respond(&settings.root.join("file.txt"), &request("1.1", fields), settings).unwrap()
+    }
+
+    // the content type and the body
+    fn list(settings: &Settings, target: &str, fields: &[(&str, &str)]) -> (String, String)
+    {
+        let fields = fields.iter().map(|(name, value)| format!("{name}: {value}\r\n"))
+            .collect::<String>();
+
+        let input = format!("GET {target} HTTP/1.1\r\n{fields}\r\n");
+        let request = PartialRequest::new().parse(input.as_bytes()).unwrap().unwrap();
+
+        let directory = settings.resolve_path(&request.header.target).unwrap();
+        let response = listing(&directory, &request, settings).unwrap();
+
+        let content_type = response.get_header("Content-Type").unwrap().to_owned();
+
+        (content_type, String::from_utf8(body(response)).unwrap())
+    }
+
+    // names in the order the json listing has them
+    fn json_names(json: &str) -> Vec<String>
+    {
+        json.split("{\"name\":\"").skip(1)
+            .map(|entry| entry.split('"').next().unwrap().to_owned())
+            .collect()
+    }
+
+    // what would end up on the wire, streams only get read up to their length
+    fn body(response: Response) -> Vec<u8>
+    {
+        let length = response.content_length().unwrap_or(u64::MAX);
+
+        let mut body = Vec::new();
+        match response.into_body().unwrap()
+        {
+            http::Body::Bytes(bytes) => body = bytes,
+            http::Body::Reader(reader) =>
+            {
+                reader.take(length).read_to_end(&mut body).unwrap();
+            }
+        }
+
+        body
+    }
+
+    #[test]
+    fn answers_conditional_gets()
+    {
+        let (_base, settings) = test_file("conditional", b"hello");
+        let get = |fields: &[(&str, &str)]| get(&settings, fields);
+
+        let response = get(&[]);
+        assert_eq!(response.status().code(), 200);
+
+        let etag = response.get_header("ETag").unwrap().to_owned();
+        let modified = response.get_header("Last-Modified").unwrap().to_owned();
+
+        let response = get(&[("If-None-Match", &etag)]);
+        assert_eq!(response.status().code(), 304);
+        assert_eq!(response.get_header("ETag"), Some(etag.as_str()));
+        assert_eq!(response.get_header("Last-Modified"), Some(modified.as_str()));
+
+        let weak = format!("\"other\", W/{etag}");
+        assert_eq!(get(&[("If-None-Match", &weak)]).status().code(), 304);
+        assert_eq!(get(&[("If-None-Match", "*")]).status().code(), 304);
+        assert_eq!(get(&[("If-None-Match", "\"other\"")]).status().code(), 200);
+
+        assert_eq!(get(&[("If-Modified-Since", &modified)]).status().code(), 304);
+
+        let later = "Fri, 31 Dec 9999 23:59:59 GMT";
+        assert_eq!(get(&[("If-Modified-Since", later)]).status().code(), 304);
+
+        let earlier = "Thu, 01 Jan 1970 00:00:00 GMT";
+        assert_eq!(get(&[("If-Modified-Since", earlier)]).status().code(), 200);
+
+        // tags win over dates when both are there
+        let both = [("If-None-Match", "\"other\""), ("If-Modified-Since", later)];
+        assert_eq!(get(&both).status().code(), 200);
+
+        // dates that dont fit anywhere are just ignored
+        let huge = "Thu, 01 Jan 300000000000 00:00:00 GMT";
+        assert_eq!(get(&[("If-Modified-Since", huge)]).status().code(), 200);
+    }
+
+    #[test]
+    fn negotiates_compression()
+    {
+        let text = "compress me ".repeat(200);
+
+        let (_base, mut settings) = test_file("negotiate", text.as_bytes());
+        settings.compression.enabled = true;
+
+        let path = settings.root.join("file.txt");
+        let accept = [("Accept-Encoding", "gzip;q=0.5, br;q=0")];
+
+        let response = respond(&path, &request("1.1", &accept), &settings).unwrap();
+        assert_eq!(response.get_header("Content-Encoding"), Some("gzip"));
+        assert_eq!(response.get_header("Vary"), Some("Accept-Encoding"));
+        assert!(response.content_length().is_none());
+
+        // a resumed download would get plain bytes at compressed offsets
+        assert_eq!(response.get_header("Accept-Ranges"), None);
+
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(&body(response)[..]).read_to_string(&mut decoded).unwrap();
+        assert_eq!(decoded, text);
+
+        // no chunked encoding in http/1.0 so it gets the plain file with a length
+        let response = respond(&path, &request("1.0", &accept), &settings).unwrap();
+        assert_eq!(response.get_header("Content-Encoding"), None);
+        assert_eq!(response.content_length(), Some(text.len() as u64));
+
+        // ranges get the plain file too
+        let ranged = [accept[0], ("Range", "bytes=0-3")];
+        let response = respond(&path, &request("1.1", &ranged), &settings).unwrap();
+        assert_eq!(response.get_header("Content-Encoding"), None);
+        assert_eq!(body(response), b"comp");
+
+        let response = respond(&path, &request("1.1", &[]), &settings).unwrap();
+        assert_eq!(response.get_header("Content-Encoding"), None);
+        assert_eq!(response.get_header("Accept-Ranges"), Some("bytes"));
+
+        // media, archives and anything unknown keep their length and ranges
+        for name in ["video.mp4", "archive.zip", "file.gz", "LICENSE"]
+        {
+            let path = settings.root.join(name);
+            fs::write(&path, text.as_bytes()).unwrap();
+
+            let response = respond(&path, &request("1.1", &accept), &settings).unwrap();
+            assert_eq!(response.get_header("Content-Encoding"), None, "{name}");
+            assert_eq!(response.get_header("Content-Type"), Some("application/octet-stream"));
+            assert_eq!(response.get_header("Accept-Ranges"), Some("bytes"), "{name}");
+            assert_eq!(response.content_length(), Some(text.len() as u64), "{name}");
+        }
+
+        // a precompressed sibling gets picked even for http/1.0 since it has a length
+        fs::write(settings.root.join("file.txt.gz"), b"pretend gzip").unwrap();
+
+        let response = respond(&path, &request("1.0", &accept), &settings).unwrap();
+        assert_eq!(response.get_header("Content-Encoding"), Some("gzip"));
+        assert_eq!(body(response), b"pretend gzip");
+    }
+
+    #[test]
+    fn serves_byte_ranges()
+    {
+        let (_base, settings) = test_file("ranges", b"0123456789");
+        let get = |fields: &[(&str, &str)]| get(&settings, fields);
+
+        let response = get(&[("Range", "bytes=2-4")]);
+        assert_eq!(response.status().code(), 206);
+        assert_eq!(response.get_header("Content-Range"), Some("bytes 2-4/10"));
+        assert_eq!(response.content_length(), Some(3));
+        assert_eq!(body(response), b"234");
+
+        let response = get(&[("Range", "bytes=-3")]);
+        assert_eq!(response.get_header("Content-Range"), Some("bytes 7-9/10"));
+        assert_eq!(body(response), b"789");
+
+        let response = get(&[("Range", "bytes=6-")]);
+        assert_eq!(response.get_header("Content-Range"), Some("bytes 6-9/10"));
+        assert_eq!(body(response), b"6789");
+
+        let response = get(&[("Range", "bytes=10-20")]);
+        assert_eq!(response.status().code(), 416);
+        assert_eq!(response.get_header("Content-Range"), Some("bytes */10"));
+
+        // malformed ranges just get the whole thing
+        let response = get(&[("Range", "bytes=4-2")]);
+        assert_eq!(response.status().code(), 200);
+        assert_eq!(body(response), b"0123456789");
+    }
+
+    #[test]
+    fn checks_if_range()
+    {
+        let (_base, settings) = test_file("if_range", b"0123456789");
+        let get = |fields: &[(&str, &str)]| get(&settings, fields);
+
+        let response = get(&[]);
+        let etag = response.get_header("ETag").unwrap().to_owned();
+        let modified = response.get_header("Last-Modified").unwrap().to_owned();
+
+        let ranged = |if_range: &str|
+        {
+            get(&[("Range", "bytes=0-1"), ("If-Range", if_range)]).status().code()
+        };
+
+        assert_eq!(ranged(&etag), 206);
+        assert_eq!(ranged(&modified), 206);
+
+        assert_eq!(ranged("\"other\""), 200);
+        assert_eq!(ranged(&format!("W/{etag}")), 200);
+        assert_eq!(ranged("Thu, 01 Jan 1970 00:00:00 GMT"), 200);
+    }
+
+    #[test]
+    fn serves_multiple_byte_ranges()
+    {
+        let (_base, settings) = test_file("multiple_ranges", b"0123456789");
+
+        let response = get(&settings, &[("Range", "bytes=0-1, 8-")]);
+        assert_eq!(response.status().code(), 206);
+
+        let content_type = format!("multipart/byteranges; boundary={RANGES_BOUNDARY}");
+        assert_eq!(response.get_header("Content-Type"), Some(content_type.as_str()));
+
+        let length = response.content_length();
+
+        let part = |range: &str, data: &str|
+        {
+            format!(
+                "\r\n--{RANGES_BOUNDARY}\r\nContent-Type: text/plain\r\n\
+                Content-Range: bytes {range}/10\r\n\r\n{data}"
+            )
+        };
+
+        let end = format!("\r\n--{RANGES_BOUNDARY}--\r\n");
+        let expected = part("0-1", "01") + &part("8-9", "89") + &end;
+
+        let Some(http::Body::Reader(mut reader)) = response.into_body() else { panic!() };
+
+        let mut sent = String::new();
+        reader.read_to_string(&mut sent).unwrap();
+
+        // the promised length has to be exactly what gets sent
+        assert_eq!(sent, expected);
+        assert_eq!(length, Some(expected.len() as u64));
+    }
+
+    #[test]
+    fn sorts_listings()
+    {
+        let (_base, settings) = test_file("listing", b"0123456789");
+        let root = &settings.root;
+
+        fs::write(root.join("a.txt"), b"aaa").unwrap();
+        fs::write(root.join("b.txt"), b"b").unwrap();
+        fs::write(root.join(".hidden"), b"hidden").unwrap();
+        fs::create_dir_all(root.join("zdir")).unwrap();
+        fs::create_dir_all(root.join("adir")).unwrap();
+
+        for (name, seconds) in [("a.txt", 3000), ("file.txt", 2000), ("b.txt", 1000)]
+        {
+            let file = File::options().write(true).open(root.join(name)).unwrap();
+            file.set_modified(UNIX_EPOCH + std::time::Duration::from_secs(seconds)).unwrap();
+        }
+
+        let names = |query: &str|
+        {
+            json_names(&list(&settings, &format!("/?format=json&{query}"), &[]).1)
+        };
+
+        // directories first, hidden files nowhere
+        assert_eq!(names(""), ["adir", "zdir", "a.txt", "b.txt", "file.txt"]);
+        assert_eq!(names("order=desc"), ["zdir", "adir", "file.txt", "b.txt", "a.txt"]);
+        assert_eq!(names("sort=size")[2..], ["b.txt", "a.txt", "file.txt"]);
+        assert_eq!(names("sort=size&order=desc")[2..], ["file.txt", "a.txt", "b.txt"]);
+        assert_eq!(names("sort=modified")[2..], ["b.txt", "file.txt", "a.txt"]);
+        assert_eq!(names("sort=nonsense")[2..], ["a.txt", "b.txt", "file.txt"]);
+
+        let (content_type, json) = list(&settings, "/", &[("Accept", "application/json")]);
+        assert_eq!(content_type, "application/json");
+        let entry = "{\"name\":\"a.txt\",\"type\":\"file\",\"size\":3,\"modified\":3000}";
+        assert!(json.contains(entry), "{json}");
+        assert!(json.contains("{\"name\":\"adir\",\"type\":\"directory\","));
+        assert!(!json.contains("hidden"));
+
+        let (content_type, html) = list(&settings, "/", &[("Accept", "text/html")]);
+        assert!(content_type.starts_with("text/html"));
+        assert!(html.contains("<a href=\"/adir/\">adir/</a>"));
+        assert!(html.contains("<a href=\"/a.txt\">a.txt</a></td><td>3</td>"));
+        assert!(!html.contains("../"));
+        assert!(!html.contains("hidden"));
+
+        // the active column links to the other order
+        assert!(html.contains("href=\"?sort=name&amp;order=desc\""));
+        assert!(html.contains("href=\"?sort=size&amp;order=asc\""));
+
+        let (_, html) = list(&settings, "/?sort=size", &[]);
+        assert!(html.contains("href=\"?sort=name&amp;order=asc\""));
+        assert!(html.contains("href=\"?sort=size&amp;order=desc\""));
+
+        fs::write(root.join("adir").join("inner.txt"), b"inner").unwrap();
+
+        let (_, html) = list(&settings, "/adir/", &[]);
+        assert!(html.contains("<title>index of /adir/</title>"));
+        assert!(html.contains("<a href=\"../\">../</a>"));
+        assert!(html.contains("<a href=\"/adir/inner.txt\">inner.txt</a>"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn escapes_hostile_names()
+    {
+        let (_base, settings) = test_file("hostile_listing", b"");
+
+        fs::write(settings.root.join("<img src=x onerror=\"a('b')\">&.txt"), b"").unwrap();
+        fs::write(settings.root.join("q\"\\\u{1}.txt"), b"").unwrap();
+
+        let (_, html) = list(&settings, "/", &[]);
+        assert!(!html.contains("<img"));
+
+        let name = ">&lt;img src=x onerror=&quot;a(&#39;b&#39;)&quot;&gt;&amp;.txt</a>";
+        assert!(html.contains(name), "{html}");
+
+        let href = "href=\"/%3Cimg%20src%3Dx%20onerror%3D%22a%28%27b%27%29%22%3E%26.txt\"";
+        assert!(html.contains(href), "{html}");
+
+        let (_, json) = list(&settings, "/?format=json", &[]);
+        assert!(json.contains("\"name\":\"<img src=x onerror=\\\"a('b')\\\">&.txt\""));
+        assert!(json.contains("\"name\":\"q\\\"\\\\\\u0001.txt\""));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn lists_symlinks_inside_the_root_only()
+    {
+        let (base, settings) = test_file("symlink_listing", b"");
+
+        fs::write(base.join("secret.txt"), b"secret outside the root").unwrap();
+        fs::create_dir_all(base.join("outside")).unwrap();
+        fs::create_dir_all(settings.root.join("inside")).unwrap();
+
+        let symlink = |target: &Path, name: &str|
+        {
+            std::os::unix::fs::symlink(target, settings.root.join(name)).unwrap();
+        };
+
+        symlink(&base.join("secret.txt"), "secret.txt");
+        symlink(&base.join("outside"), "outside");
+        symlink(&base.join("missing"), "broken");
+        symlink(&settings.root.join("inside"), "linked");
+        symlink(&settings.root.join("file.txt"), "linked.txt");
+
+        let (_, json) = list(&settings, "/?format=json", &[]);
+        assert_eq!(json_names(&json), ["inside", "linked", "file.txt", "linked.txt"]);
+        assert!(json.contains("{\"name\":\"linked\",\"type\":\"directory\","));
+
+        let (_, html) = list(&settings, "/", &[]);
+        assert!(!html.contains("secret"));
+        assert!(!html.contains("outside"));
+    }
+}
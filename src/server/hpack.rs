@@ -0,0 +1,524 @@
+// header compression for http2 (rfc 7541)
+// only the decoding side keeps a dynamic table, everything sent goes out as plain literals
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::OnceLock
+};
+
+
+// the table size every connection starts with, the server never advertises a bigger one
+pub const MAX_TABLE_SIZE: usize = 4096;
+
+// indexed fields can point at big table entries so the block size alone doesnt bound this
+pub const MAX_HEADER_LIST: usize = 64 * 1024;
+
+// every table entry costs this much on top of its name and value
+const ENTRY_OVERHEAD: usize = 32;
+
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", "")
+];
+
+// (code, length in bits) for every byte and the end of string marker at 256
+const HUFFMAN_CODES: [(u32, u8); 257] = [
+    (0x1ff8, 13), (0x7fffd8, 23), (0xfffffe2, 28), (0xfffffe3, 28), (0xfffffe4, 28),
+    (0xfffffe5, 28), (0xfffffe6, 28), (0xfffffe7, 28), (0xfffffe8, 28), (0xffffea, 24),
+    (0x3ffffffc, 30), (0xfffffe9, 28), (0xfffffea, 28), (0x3ffffffd, 30), (0xfffffeb, 28),
+    (0xfffffec, 28), (0xfffffed, 28), (0xfffffee, 28), (0xfffffef, 28), (0xffffff0, 28),
+    (0xffffff1, 28), (0xffffff2, 28), (0x3ffffffe, 30), (0xffffff3, 28), (0xffffff4, 28),
+    (0xffffff5, 28), (0xffffff6, 28), (0xffffff7, 28), (0xffffff8, 28), (0xffffff9, 28),
+    (0xffffffa, 28), (0xffffffb, 28), (0x14, 6), (0x3f8, 10), (0x3f9, 10), (0xffa, 12),
+    (0x1ff9, 13), (0x15, 6), (0xf8, 8), (0x7fa, 11), (0x3fa, 10), (0x3fb, 10), (0xf9, 8),
+    (0x7fb, 11), (0xfa, 8), (0x16, 6), (0x17, 6), (0x18, 6), (0x0, 5), (0x1, 5), (0x2, 5),
+    (0x19, 6), (0x1a, 6), (0x1b, 6), (0x1c, 6), (0x1d, 6), (0x1e, 6), (0x1f, 6), (0x5c, 7),
+    (0xfb, 8), (0x7ffc, 15), (0x20, 6), (0xffb, 12), (0x3fc, 10), (0x1ffa, 13), (0x21, 6),
+    (0x5d, 7), (0x5e, 7), (0x5f, 7), (0x60, 7), (0x61, 7), (0x62, 7), (0x63, 7), (0x64, 7),
+    (0x65, 7), (0x66, 7), (0x67, 7), (0x68, 7), (0x69, 7), (0x6a, 7), (0x6b, 7), (0x6c, 7),
+    (0x6d, 7), (0x6e, 7), (0x6f, 7), (0x70, 7), (0x71, 7), (0x72, 7), (0xfc, 8), (0x73, 7),
+    (0xfd, 8), (0x1ffb, 13), (0x7fff0, 19), (0x1ffc, 13), (0x3ffc, 14), (0x22, 6), (0x7ffd, 15),
+    (0x3, 5), (0x23, 6), (0x4, 5), (0x24, 6), (0x5, 5), (0x25, 6), (0x26, 6), (0x27, 6), (0x6, 5),
+    (0x74, 7), (0x75, 7), (0x28, 6), (0x29, 6), (0x2a, 6), (0x7, 5), (0x2b, 6), (0x76, 7),
+    (0x2c, 6), (0x8, 5), (0x9, 5), (0x2d, 6), (0x77, 7), (0x78, 7), (0x79, 7), (0x7a, 7),
+    (0x7b, 7), (0x7ffe, 15), (0x7fc, 11), (0x3ffd, 14), (0x1ffd, 13), (0xffffffc, 28),
+    (0xfffe6, 20), (0x3fffd2, 22), (0xfffe7, 20), (0xfffe8, 20), (0x3fffd3, 22), (0x3fffd4, 22),
+    (0x3fffd5, 22), (0x7fffd9, 23), (0x3fffd6, 22), (0x7fffda, 23), (0x7fffdb, 23), (0x7fffdc, 23),
+    (0x7fffdd, 23), (0x7fffde, 23), (0xffffeb, 24), (0x7fffdf, 23), (0xffffec, 24), (0xffffed, 24),
+    (0x3fffd7, 22), (0x7fffe0, 23), (0xffffee, 24), (0x7fffe1, 23), (0x7fffe2, 23), (0x7fffe3, 23),
+    (0x7fffe4, 23), (0x1fffdc, 21), (0x3fffd8, 22), (0x7fffe5, 23), (0x3fffd9, 22), (0x7fffe6, 23),
+    (0x7fffe7, 23), (0xffffef, 24), (0x3fffda, 22), (0x1fffdd, 21), (0xfffe9, 20), (0x3fffdb, 22),
+    (0x3fffdc, 22), (0x7fffe8, 23), (0x7fffe9, 23), (0x1fffde, 21), (0x7fffea, 23), (0x3fffdd, 22),
+    (0x3fffde, 22), (0xfffff0, 24), (0x1fffdf, 21), (0x3fffdf, 22), (0x7fffeb, 23), (0x7fffec, 23),
+    (0x1fffe0, 21), (0x1fffe1, 21), (0x3fffe0, 22), (0x1fffe2, 21), (0x7fffed, 23), (0x3fffe1, 22),
+    (0x7fffee, 23), (0x7fffef, 23), (0xfffea, 20), (0x3fffe2, 22), (0x3fffe3, 22), (0x3fffe4, 22),
+    (0x7ffff0, 23), (0x3fffe5, 22), (0x3fffe6, 22), (0x7ffff1, 23), (0x3ffffe0, 26),
+    (0x3ffffe1, 26), (0xfffeb, 20), (0x7fff1, 19), (0x3fffe7, 22), (0x7ffff2, 23), (0x3fffe8, 22),
+    (0x1ffffec, 25), (0x3ffffe2, 26), (0x3ffffe3, 26), (0x3ffffe4, 26), (0x7ffffde, 27),
+    (0x7ffffdf, 27), (0x3ffffe5, 26), (0xfffff1, 24), (0x1ffffed, 25), (0x7fff2, 19),
+    (0x1fffe3, 21), (0x3ffffe6, 26), (0x7ffffe0, 27), (0x7ffffe1, 27), (0x3ffffe7, 26),
+    (0x7ffffe2, 27), (0xfffff2, 24), (0x1fffe4, 21), (0x1fffe5, 21), (0x3ffffe8, 26),
+    (0x3ffffe9, 26), (0xffffffd, 28), (0x7ffffe3, 27), (0x7ffffe4, 27), (0x7ffffe5, 27),
+    (0xfffec, 20), (0xfffff3, 24), (0xfffed, 20), (0x1fffe6, 21), (0x3fffe9, 22), (0x1fffe7, 21),
+    (0x1fffe8, 21), (0x7ffff3, 23), (0x3fffea, 22), (0x3fffeb, 22), (0x1ffffee, 25),
+    (0x1ffffef, 25), (0xfffff4, 24), (0xfffff5, 24), (0x3ffffea, 26), (0x7ffff4, 23),
+    (0x3ffffeb, 26), (0x7ffffe6, 27), (0x3ffffec, 26), (0x3ffffed, 26), (0x7ffffe7, 27),
+    (0x7ffffe8, 27), (0x7ffffe9, 27), (0x7ffffea, 27), (0x7ffffeb, 27), (0xffffffe, 28),
+    (0x7ffffec, 27), (0x7ffffed, 27), (0x7ffffee, 27), (0x7ffffef, 27), (0x7fffff0, 27),
+    (0x3ffffee, 26), (0x3fffffff, 30)
+];
+
+const HUFFMAN_EOS: u16 = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError
+{
+    Truncated,
+    IntegerOverflow,
+    InvalidIndex,
+    InvalidHuffman,
+    TableSizeTooLarge,
+    LateSizeUpdate,
+    HeaderListTooLarge
+}
+
+pub type Field = (Vec<u8>, Vec<u8>);
+
+pub struct Decoder
+{
+    table: VecDeque<Field>,
+    size: usize,
+    max_size: usize
+}
+
+impl Decoder
+{
+    pub fn new() -> Self
+    {
+        Self{table: VecDeque::new(), size: 0, max_size: MAX_TABLE_SIZE}
+    }
+
+    pub fn decode(&mut self, block: &[u8]) -> Result<Vec<Field>, DecodeError>
+    {
+        let mut input = block;
+
+        let mut fields = Vec::new();
+        let mut list_size = 0;
+
+        while let Some(&first) = input.first()
+        {
+            let field = if first & 0x80 != 0
+            {
+                let index = decode_integer(&mut input, 7)?;
+
+                self.get(index)?.clone()
+            } else if first & 0xc0 == 0x40
+            {
+                let field = self.literal(&mut input, 6)?;
+                self.insert(field.clone());
+
+                field
+            } else if first & 0xe0 == 0x20
+            {
+                // size updates can only come before the first field of a block
+                if !fields.is_empty()
+                {
+                    return Err(DecodeError::LateSizeUpdate);
+                }
+
+                let size = decode_integer(&mut input, 5)?;
+                if size > MAX_TABLE_SIZE
+                {
+                    return Err(DecodeError::TableSizeTooLarge);
+                }
+
+                self.max_size = size;
+                self.evict();
+
+                continue;
+            } else
+            {
+                // without indexing and never indexed only differ for proxies
+                self.literal(&mut input, 4)?
+            };
+
+            list_size += field.0.len() + field.1.len() + ENTRY_OVERHEAD;
+            if list_size > MAX_HEADER_LIST
+            {
+                return Err(DecodeError::HeaderListTooLarge);
+            }
+
+            fields.push(field);
+        }
+
+        Ok(fields)
+    }
+
+    fn literal(&self, input: &mut &[u8], prefix: u32) -> Result<Field, DecodeError>
+    {
+        let index = decode_integer(input, prefix)?;
+
+        let name = if index == 0
+        {
+            decode_string(input)?
+        } else
+        {
+            self.get(index)?.0.clone()
+        };
+
+        Ok((name, decode_string(input)?))
+    }
+
+    fn get(&self, index: usize) -> Result<&Field, DecodeError>
+    {
+        static TABLE: OnceLock<Vec<Field>> = OnceLock::new();
+
+        let static_table = TABLE.get_or_init(||
+        {
+            STATIC_TABLE.iter().map(|(name, value)|
+            {
+                (name.as_bytes().to_vec(), value.as_bytes().to_vec())
+            }).collect()
+        });
+
+        match index
+        {
+            0 => Err(DecodeError::InvalidIndex),
+            index if index <= static_table.len() => Ok(&static_table[index - 1]),
+            index => self.table.get(index - static_table.len() - 1)
+                .ok_or(DecodeError::InvalidIndex)
+        }
+    }
+
+    fn insert(&mut self, field: Field)
+    {
+        let size = field.0.len() + field.1.len() + ENTRY_OVERHEAD;
+
+        // an entry bigger than the whole table just empties it
+        self.size += size;
+        self.table.push_front(field);
+
+        self.evict();
+    }
+
+    fn evict(&mut self)
+    {
+        while self.size > self.max_size
+        {
+            let (name, value) = self.table.pop_back().expect("size counts the entries");
+
+            self.size -= name.len() + value.len() + ENTRY_OVERHEAD;
+        }
+    }
+}
+
+// literals without indexing and without huffman, so the clients table never matters
+pub fn encode(fields: &[(String, String)]) -> Vec<u8>
+{
+    let mut block = Vec::new();
+
+    fields.iter().for_each(|(name, value)|
+    {
+        block.push(0);
+
+        [name, value].into_iter().for_each(|s|
+        {
+            encode_integer(&mut block, 0, 7, s.len());
+            block.extend(s.as_bytes());
+        });
+    });
+
+    block
+}
+
+fn encode_integer(block: &mut Vec<u8>, flags: u8, prefix: u32, value: usize)
+{
+    let max = (1 << prefix) - 1;
+
+    if value < max
+    {
+        block.push(flags | value as u8);
+
+        return;
+    }
+
+    block.push(flags | max as u8);
+
+    let mut rest = value - max;
+    while rest >= 0x80
+    {
+        block.push((rest & 0x7f) as u8 | 0x80);
+        rest >>= 7;
+    }
+
+    block.push(rest as u8);
+}
+
+fn decode_integer(input: &mut &[u8], prefix: u32) -> Result<usize, DecodeError>
+{
+    let (&first, rest) = input.split_first().ok_or(DecodeError::Truncated)?;
+    *input = rest;
+
+    let max = (1 << prefix) - 1;
+
+    let mut value = (first & max as u8) as usize;
+    if value < max
+    {
+        return Ok(value);
+    }
+
+    // nothing in a header block needs more than 28 bits worth of continuation
+    for shift in (0..=28).step_by(7)
+    {
+        let (&byte, rest) = input.split_first().ok_or(DecodeError::Truncated)?;
+        *input = rest;
+
+        value = value.checked_add(((byte & 0x7f) as usize) << shift)
+            .ok_or(DecodeError::IntegerOverflow)?;
+
+        if byte & 0x80 == 0
+        {
+            return Ok(value);
+        }
+    }
+
+    Err(DecodeError::IntegerOverflow)
+}
+
+fn decode_string(input: &mut &[u8]) -> Result<Vec<u8>, DecodeError>
+{
+    let is_huffman = input.first().ok_or(DecodeError::Truncated)? & 0x80 != 0;
+    let length = decode_integer(input, 7)?;
+
+    if length > input.len()
+    {
+        return Err(DecodeError::Truncated);
+    }
+
+    let (data, rest) = input.split_at(length);
+    *input = rest;
+
+    if is_huffman
+    {
+        huffman_decode(data)
+    } else
+    {
+        Ok(data.to_vec())
+    }
+}
+
+fn huffman_decode(data: &[u8]) -> Result<Vec<u8>, DecodeError>
+{
+    static CODES: OnceLock<HashMap<(u32, u8), u16>> = OnceLock::new();
+
+    let codes = CODES.get_or_init(||
+    {
+        HUFFMAN_CODES.iter().enumerate().map(|(symbol, &code)| (code, symbol as u16)).collect()
+    });
+
+    let mut decoded = Vec::with_capacity(data.len() * 8 / 5);
+
+    let mut code = 0_u32;
+    let mut length = 0_u8;
+
+    for bit in data.iter().flat_map(|byte| (0..8).rev().map(move |shift| (byte >> shift) & 1))
+    {
+        code = (code << 1) | bit as u32;
+        length += 1;
+
+        match codes.get(&(code, length))
+        {
+            Some(&HUFFMAN_EOS) => return Err(DecodeError::InvalidHuffman),
+            Some(&symbol) =>
+            {
+                decoded.push(symbol as u8);
+
+                code = 0;
+                length = 0;
+            },
+            // the longest code is 30 bits
+            None if length >= 30 => return Err(DecodeError::InvalidHuffman),
+            None => ()
+        }
+    }
+
+    // whats left over has to be a prefix of the end of string marker, so all ones
+    if length > 7 || code != (1 << length) - 1
+    {
+        return Err(DecodeError::InvalidHuffman);
+    }
+
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+
+    fn pairs(fields: &[Field]) -> Vec<(&str, &str)>
+    {
+        fields.iter().map(|(name, value)|
+        {
+            (std::str::from_utf8(name).unwrap(), std::str::from_utf8(value).unwrap())
+        }).collect()
+    }
+
+    #[test]
+    fn decodes_rfc_examples()
+    {
+        let mut decoder = Decoder::new();
+
+        // rfc 7541 c.4.1 and c.4.2, huffman coded and then reusing the dynamic table
+        let first = [
+            0x82, 0x86, 0x84, 0x41, 0x8c, 0xf1, 0xe3, 0xc2, 0xe5, 0xf2, 0x3a, 0x6b, 0xa0, 0xab,
+            0x90, 0xf4, 0xff
+        ];
+
+        assert_eq!(pairs(&decoder.decode(&first).unwrap()), [
+            (":method", "GET"),
+            (":scheme", "http"),
+            (":path", "/"),
+            (":authority", "www.example.com")
+        ]);
+
+        let second = [0x82, 0x86, 0x84, 0xbe, 0x58, 0x86, 0xa8, 0xeb, 0x10, 0x64, 0x9c, 0xbf];
+
+        assert_eq!(pairs(&decoder.decode(&second).unwrap()), [
+            (":method", "GET"),
+            (":scheme", "http"),
+            (":path", "/"),
+            (":authority", "www.example.com"),
+            ("cache-control", "no-cache")
+        ]);
+    }
+
+    #[test]
+    fn round_trips_literals()
+    {
+        let fields = vec![
+            (":status".to_owned(), "200".to_owned()),
+            ("x-long".to_owned(), "a".repeat(300))
+        ];
+
+        let decoded = Decoder::new().decode(&encode(&fields)).unwrap();
+
+        assert_eq!(pairs(&decoded), [(":status", "200"), ("x-long", "a".repeat(300).as_str())]);
+    }
+
+    #[test]
+    fn rejects_malformed_blocks()
+    {
+        let decode = |block: &[u8]| Decoder::new().decode(block);
+
+        // a size update prefix with nothing after it
+        assert_eq!(decode(&[0x3f]), Err(DecodeError::Truncated));
+
+        assert!(decode(&[0x3f, 0xe1, 0x1f]).is_ok());
+        assert_eq!(decode(&[0x3f, 0xe2, 0x1f]), Err(DecodeError::TableSizeTooLarge));
+        assert_eq!(decode(&[0x82, 0x20]), Err(DecodeError::LateSizeUpdate));
+
+        let huge = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01];
+        assert_eq!(decode(&huge), Err(DecodeError::IntegerOverflow));
+
+        assert_eq!(decode(&[0x80]), Err(DecodeError::InvalidIndex));
+        assert_eq!(decode(&[0xbe]), Err(DecodeError::InvalidIndex));
+
+        assert_eq!(decode(&[0x00, 0x05, b'a']), Err(DecodeError::Truncated));
+
+        // padding thats not all ones and a whole byte of padding
+        assert_eq!(decode(&[0x00, 0x81, 0x00, 0x00]), Err(DecodeError::InvalidHuffman));
+        assert_eq!(decode(&[0x00, 0x82, 0x1f, 0xff, 0x00]), Err(DecodeError::InvalidHuffman));
+    }
+
+    #[test]
+    fn limits_header_list_size()
+    {
+        let mut block = encode(&[("x-big".to_owned(), "a".repeat(4000))]);
+        block[0] = 0x40;
+
+        // every repeat only costs a byte but points at the whole entry again
+        block.extend([0xbe; 20]);
+
+        assert_eq!(Decoder::new().decode(&block), Err(DecodeError::HeaderListTooLarge));
+    }
+
+    #[test]
+    fn evicts_old_entries()
+    {
+        let mut decoder = Decoder::new();
+
+        let mut block = encode(&[("x-a".to_owned(), "a".repeat(3000))]);
+        let second = block.len();
+
+        block.extend(encode(&[("x-b".to_owned(), "b".repeat(3000))]));
+
+        block[0] = 0x40;
+        block[second] = 0x40;
+
+        decoder.decode(&block).unwrap();
+
+        assert_eq!(decoder.table.len(), 1);
+        assert_eq!(decoder.get(62).unwrap().0, b"x-b");
+    }
+}
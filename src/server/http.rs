@@ -1,15 +1,40 @@
 use std::{
     fmt,
-    num::ParseIntError
+    io::{self, Read, Write},
+    num::ParseIntError,
+    time::{SystemTime, UNIX_EPOCH}
 };
 
 
+pub const MAX_TARGET_LENGTH: usize = 8 * 1024;
+pub const MAX_HEADER_LINE: usize = 8 * 1024;
+pub const MAX_HEADER_FIELDS: usize = 100;
+pub const MAX_BODY_LENGTH: usize = 64 * 1024 * 1024;
+
+pub const MAX_RANGES: usize = 16;
+
+pub const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+pub const SERVER_NAME: &str = concat!("funserver/", env!("CARGO_PKG_VERSION"));
+
+
 #[derive(Debug)]
 pub enum Error
 {
     Request(RequestError)
 }
 
+impl Error
+{
+    pub fn status(&self) -> Status
+    {
+        match self
+        {
+            Error::Request(err) => err.status()
+        }
+    }
+}
+
 impl From<RequestError> for Error
 {
     fn from(value: RequestError) -> Self
@@ -28,16 +53,24 @@ impl fmt::Display for Error
             {
                 match err
                 {
-                    RequestError::HeaderMissing => "no header line".to_owned(),
                     RequestError::RequestTypeMissing => "request type missing".to_owned(),
                     RequestError::UnknownRequestType(x) => format!("unknown request type ({x})"),
                     RequestError::BodyMissing => "request header is missing body".to_owned(),
+                    RequestError::MalformedTarget(x) => format!("request target is malformed ({x})"),
                     RequestError::VersionMissing => "request header missing version".to_owned(),
                     RequestError::MalformedVersion => "request header version is malformed".to_owned(),
                     RequestError::InvalidMajor => "major version number is malformed".to_owned(),
                     RequestError::UnsupportedMajor => "major version must be 1".to_owned(),
                     RequestError::InvalidMinor => "minor version number is malformed".to_owned(),
                     RequestError::MultipartNoBoundary => "multipart request doesnt have a boundary".to_owned(),
+                    RequestError::MalformedPart(x) => format!("multipart part is malformed ({x})"),
+                    RequestError::TargetTooLong => "request target is too long".to_owned(),
+                    RequestError::HeaderTooLarge => "request header is too large".to_owned(),
+                    RequestError::LengthRequired => "request body has no length".to_owned(),
+                    RequestError::AmbiguousLength => "request body length is ambiguous".to_owned(),
+                    RequestError::BodyTooLarge(x) => format!("request body is too large ({x} bytes)"),
+                    RequestError::MalformedChunk => "chunked body is malformed".to_owned(),
+                    RequestError::UnsupportedEncoding(x) => format!("unsupported transfer encoding ({x})"),
                     RequestError::ParseIntError(x) => format!("error parsing integer ({x})")
                 }
             }
@@ -50,19 +83,56 @@ impl fmt::Display for Error
 #[derive(Debug)]
 pub enum RequestError
 {
-    HeaderMissing,
     RequestTypeMissing,
     UnknownRequestType(String),
     BodyMissing,
+    MalformedTarget(String),
     VersionMissing,
     MalformedVersion,
     InvalidMajor,
     UnsupportedMajor,
     InvalidMinor,
     MultipartNoBoundary,
+    MalformedPart(String),
+    TargetTooLong,
+    HeaderTooLarge,
+    LengthRequired,
+    AmbiguousLength,
+    BodyTooLarge(usize),
+    MalformedChunk,
+    UnsupportedEncoding(String),
     ParseIntError(ParseIntError)
 }
 
+impl RequestError
+{
+    pub fn status(&self) -> Status
+    {
+        match self
+        {
+            RequestError::UnknownRequestType(_) => Status::NotImplemented,
+            RequestError::UnsupportedMajor => Status::HttpVersionNotSupported,
+            RequestError::TargetTooLong => Status::UriTooLong,
+            RequestError::HeaderTooLarge => Status::HeaderFieldsTooLarge,
+            RequestError::LengthRequired => Status::LengthRequired,
+            RequestError::BodyTooLarge(_) => Status::PayloadTooLarge,
+            RequestError::UnsupportedEncoding(_) => Status::NotImplemented,
+            RequestError::RequestTypeMissing
+            | RequestError::BodyMissing
+            | RequestError::MalformedTarget(_)
+            | RequestError::VersionMissing
+            | RequestError::MalformedVersion
+            | RequestError::InvalidMajor
+            | RequestError::InvalidMinor
+            | RequestError::MultipartNoBoundary
+            | RequestError::MalformedPart(_)
+            | RequestError::AmbiguousLength
+            | RequestError::MalformedChunk
+            | RequestError::ParseIntError(_) => Status::BadRequest
+        }
+    }
+}
+
 impl From<ParseIntError> for RequestError
 {
     fn from(value: ParseIntError) -> Self
@@ -71,11 +141,158 @@ impl From<ParseIntError> for RequestError
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum RequestType
 {
     Post,
-    Get
+    Get,
+    Head,
+    Put,
+    Delete,
+    Options,
+    Patch,
+    Connect,
+    Trace
+}
+
+impl RequestType
+{
+    pub fn parse(s: &str) -> Option<Self>
+    {
+        let request_type = match s
+        {
+            "GET" => RequestType::Get,
+            "POST" => RequestType::Post,
+            "HEAD" => RequestType::Head,
+            "PUT" => RequestType::Put,
+            "DELETE" => RequestType::Delete,
+            "OPTIONS" => RequestType::Options,
+            "PATCH" => RequestType::Patch,
+            "CONNECT" => RequestType::Connect,
+            "TRACE" => RequestType::Trace,
+            _ => return None
+        };
+
+        Some(request_type)
+    }
+
+    pub fn as_str(&self) -> &'static str
+    {
+        match self
+        {
+            RequestType::Get => "GET",
+            RequestType::Post => "POST",
+            RequestType::Head => "HEAD",
+            RequestType::Put => "PUT",
+            RequestType::Delete => "DELETE",
+            RequestType::Options => "OPTIONS",
+            RequestType::Patch => "PATCH",
+            RequestType::Connect => "CONNECT",
+            RequestType::Trace => "TRACE"
+        }
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct RequestTarget
+{
+    pub raw: String,
+    pub path: String,
+    pub segments: Vec<String>,
+    // segments with . and .. resolved, none if they climb above the root
+    pub normalized: Option<Vec<String>>,
+    pub query: Vec<(String, String)>
+}
+
+impl RequestTarget
+{
+    pub fn parse(raw: &str) -> Result<Self, RequestError>
+    {
+        let malformed = || RequestError::MalformedTarget(raw.to_owned());
+
+        let target = raw.split('#').next().expect("split always has a first element");
+
+        // absolute form (http://host/path) only cares about the path part
+        let target = ["http://", "https://"].into_iter().find_map(|scheme|
+        {
+            let rest = target.strip_prefix(scheme)?;
+
+            Some(rest.find('/').map_or("/", |index| &rest[index..]))
+        }).unwrap_or(target);
+
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+        if !path.starts_with('/') && path != "*"
+        {
+            return Err(malformed());
+        }
+
+        let segments = path.split('/').filter(|segment| !segment.is_empty()).map(|segment|
+        {
+            percent_decode(segment).ok_or_else(malformed)
+        }).collect::<Result<Vec<_>, _>>()?;
+
+        let normalized = segments.iter().try_fold(Vec::new(), |mut normalized, segment|
+        {
+            match segment.as_str()
+            {
+                "." => (),
+                ".." => normalized.pop().map(|_| ())?,
+                segment => normalized.push(segment.to_owned())
+            }
+
+            Some(normalized)
+        });
+
+        let query = parse_urlencoded(query).ok_or_else(malformed)?;
+
+        Ok(Self{raw: raw.to_owned(), path: path.to_owned(), segments, normalized, query})
+    }
+
+    pub fn decoded_path(&self) -> String
+    {
+        let mut path = self.segments.iter().fold(String::new(), |acc, segment|
+        {
+            acc + "/" + segment
+        });
+
+        if path.is_empty() || self.path.ends_with('/')
+        {
+            path.push('/');
+        }
+
+        path
+    }
+
+    // the decoded path everything should match against, /a/../b and /b are the same file
+    pub fn normalized_path(&self) -> Option<String>
+    {
+        let mut path = self.normalized.as_ref()?.iter().fold(String::new(), |acc, segment|
+        {
+            acc + "/" + segment
+        });
+
+        if path.is_empty() || self.path.ends_with('/')
+        {
+            path.push('/');
+        }
+
+        Some(path)
+    }
+
+    pub fn query_value(&self, name: &str) -> Option<&str>
+    {
+        self.query.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+
+    // the still encoded query with its ? in front, empty if there isnt one
+    pub fn query_suffix(&self) -> &str
+    {
+        let target = self.raw.split('#').next().expect("split always has a first element");
+
+        target.find('?').map_or("", |index| &target[index..])
+    }
 }
 
 #[allow(dead_code)]
@@ -83,7 +300,7 @@ pub enum RequestType
 pub struct RequestHeader
 {
     pub request: RequestType,
-    pub body: String,
+    pub target: RequestTarget,
     pub version_major: u8,
     pub version_minor: u8
 }
@@ -99,63 +316,219 @@ pub struct RequestFieldSimple
 pub struct RequestField
 {
     pub this: RequestFieldSimple,
-    pub children: Vec<RequestFieldSimple>
+    pub children: Vec<RequestFieldSimple>,
+    pub value: String
 }
 
-#[derive(Debug)]
-pub struct RequestState
+impl RequestField
 {
-    boundary: Option<String>,
-    content_length: usize,
-    is_data_part: bool,
-    is_data_part_header: bool,
-    data: Vec<DataPart>
+    pub fn is(&self, name: &str) -> bool
+    {
+        self.this.name.eq_ignore_ascii_case(name)
+    }
+
+    pub fn child(&self, name: &str) -> Option<&str>
+    {
+        self.children.iter().find(|child| child.name.eq_ignore_ascii_case(name))
+            .map(|child| child.body.as_str())
+    }
 }
 
-impl RequestState
+#[derive(Debug, Clone)]
+pub struct MediaType
+{
+    pub essence: String,
+    pub parameters: Vec<RequestFieldSimple>
+}
+
+impl MediaType
+{
+    #[allow(dead_code)]
+    pub fn parameter(&self, name: &str) -> Option<&str>
+    {
+        self.parameters.iter().find(|parameter| parameter.name.eq_ignore_ascii_case(name))
+            .map(|parameter| parameter.body.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Headers
+{
+    fields: Vec<RequestField>
+}
+
+#[allow(dead_code)]
+impl Headers
 {
-    fn is_partial(&self) -> bool
+    pub fn push(&mut self, field: RequestField)
+    {
+        self.fields.push(field);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=&RequestField>
+    {
+        self.fields.iter()
+    }
+
+    pub fn len(&self) -> usize
+    {
+        self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.fields.is_empty()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&RequestField>
+    {
+        self.fields.iter().find(|field| field.is(name))
+    }
+
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item=&'a RequestField>
+    {
+        self.fields.iter().filter(move |field| field.is(name))
+    }
+
+    pub fn value(&self, name: &str) -> Option<&str>
+    {
+        self.get(name).map(|field| field.value.as_str())
+    }
+
+    // all values of a header, including comma separated ones and repeated fields
+    pub fn values<'a>(&'a self, name: &'a str) -> impl Iterator<Item=&'a str>
+    {
+        self.get_all(name).flat_map(|field| field.value.split(','))
+            .map(|value| value.trim())
+            .filter(|value| !value.is_empty())
+    }
+
+    pub fn contains(&self, name: &str) -> bool
+    {
+        self.get(name).is_some()
+    }
+
+    pub fn content_length(&self) -> Result<Option<usize>, RequestError>
     {
-        self.boundary.is_some()
+        self.value("Content-Length").map(|value| value.parse().map_err(RequestError::from))
+            .transpose()
     }
 
-    fn consume_length(&mut self, amount: usize) -> Option<usize>
+    pub fn content_type(&self) -> Option<MediaType>
     {
-        if amount > self.content_length
+        self.get("Content-Type").map(|field|
         {
-            Some(self.content_length)
+            MediaType{
+                essence: field.this.body.to_ascii_lowercase(),
+                parameters: field.children.clone()
+            }
+        })
+    }
+
+    // host without the port
+    pub fn host(&self) -> Option<&str>
+    {
+        let host = self.value("Host")?;
+
+        let host = if host.starts_with('[')
+        {
+            host.find(']').map_or(host, |index| &host[..=index])
         } else
         {
-            self.content_length -= amount;
+            host.rsplit_once(':').map_or(host, |(host, _)| host)
+        };
+
+        Some(host)
+    }
+
+    pub fn cookies(&self) -> Vec<(String, String)>
+    {
+        self.get_all("Cookie").flat_map(|field| field.value.split(';')).filter_map(|cookie|
+        {
+            let (name, value) = cookie.trim().split_once('=')?;
 
-            None
-        }
+            let value = value.strip_prefix('"').and_then(|value| value.strip_suffix('"'))
+                .unwrap_or(value);
+
+            Some((name.to_owned(), value.to_owned()))
+        }).collect()
     }
 
-    fn last_data(&mut self) -> &mut DataPart
+    pub fn cookie(&self, name: &str) -> Option<String>
     {
-        self.data.last_mut().unwrap()
+        self.cookies().into_iter().find(|(key, _)| key == name).map(|(_, value)| value)
     }
 }
 
-impl Default for RequestState
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Phase
+{
+    #[default]
+    RequestLine,
+    Headers,
+    Body,
+    Multipart,
+    ChunkSize,
+    ChunkData,
+    ChunkEnd,
+    Trailers,
+    Done
+}
+
+#[derive(Debug, Default)]
+pub struct RequestState
+{
+    phase: Phase,
+    boundary: Option<String>,
+    content_length: usize,
+    has_length: bool,
+    is_chunked: bool,
+    body_length: usize,
+    fields_amount: usize,
+    is_data_part: bool,
+    is_data_part_header: bool,
+    is_data_end: bool
+}
+
+impl RequestState
 {
-    fn default() -> Self
+    fn end_headers(&mut self) -> Result<(), RequestError>
     {
-        Self{
-            boundary: None,
-            content_length: 0,
-            is_data_part: false,
-            is_data_part_header: false,
-            data: Vec::new()
+        // a proxy in front could have picked the other one, so theres no safe way to read this
+        if self.is_chunked && self.has_length
+        {
+            return Err(RequestError::AmbiguousLength);
         }
+
+        // chunked bodies get decoded first and split into parts after
+        self.phase = if self.is_chunked
+        {
+            Phase::ChunkSize
+        } else if self.boundary.is_some()
+        {
+            if !self.has_length
+            {
+                return Err(RequestError::LengthRequired);
+            }
+
+            // an empty multipart body has no parts to wait for
+            if self.content_length == 0 { Phase::Done } else { Phase::Multipart }
+        } else if self.content_length > 0
+        {
+            Phase::Body
+        } else
+        {
+            Phase::Done
+        };
+
+        Ok(())
     }
 }
 
 #[derive(Debug)]
 pub struct DataPart
 {
-    pub fields: Vec<RequestField>,
+    pub headers: Headers,
     pub data: Vec<u8>
 }
 
@@ -164,35 +537,138 @@ impl DataPart
     pub fn new() -> Self
     {
         Self{
-            fields: Vec::new(),
+            headers: Headers::default(),
             data: Vec::new()
         }
     }
 }
 
+// who the client is according to the certificate it sent
+#[allow(dead_code)]
+#[derive(Clone)]
+pub struct ClientIdentity
+{
+    pub common_name: Option<String>,
+    // sha256 of the certificate as colon separated hex
+    pub fingerprint: String,
+    pub certificate: Vec<u8>
+}
+
+impl fmt::Debug for ClientIdentity
+{
+    // the whole certificate would just be a wall of numbers in the logs
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        f.debug_struct("ClientIdentity")
+            .field("common_name", &self.common_name)
+            .field("fingerprint", &self.fingerprint)
+            .field("certificate", &format_args!("<{} bytes>", self.certificate.len()))
+            .finish()
+    }
+}
+
 #[derive(Debug)]
 pub struct Request
 {
     pub header: RequestHeader,
-    pub fields: Vec<RequestField>,
-    pub data: Vec<DataPart>
+    pub headers: Headers,
+    pub data: Vec<DataPart>,
+    pub body: Vec<u8>,
+    pub trailers: Headers,
+    // only there if the connection had a verified client certificate
+    pub client: Option<ClientIdentity>
 }
 
 impl Request
 {
-    fn parse_arg(text: &str) -> Result<RequestFieldSimple, RequestError>
+    // builds a request out of fields that were already split up, like the ones http2 sends
+    pub fn from_parts(
+        request_type: &str,
+        target: &str,
+        fields: &[(String, String)],
+        body: Vec<u8>,
+        trailers: &[(String, String)]
+    ) -> Result<Self, RequestError>
     {
-        if let Some(name_split) = text.find(':').or_else(||
+        let request = RequestType::parse(request_type)
+            .ok_or_else(|| RequestError::UnknownRequestType(request_type.to_owned()))?;
+
+        if target.len() > MAX_TARGET_LENGTH
         {
-            text.find('=')
-        })
+            return Err(RequestError::TargetTooLong);
+        }
+
+        let target = RequestTarget::parse(target)?;
+
+        if body.len() > MAX_BODY_LENGTH
+        {
+            return Err(RequestError::BodyTooLarge(body.len()));
+        }
+
+        let mut state = RequestState::default();
+
+        let mut parse_fields = |fields: &[(String, String)]|
+        {
+            fields.iter().try_fold(Headers::default(), |mut headers, (name, value)|
+            {
+                let line = format!("{name}: {value}");
+                headers.push(Self::parse_header(&mut state, line.as_bytes())?);
+
+                Ok::<_, RequestError>(headers)
+            })
+        };
+
+        let headers = parse_fields(fields)?;
+        let trailers = parse_fields(trailers)?;
+
+        let header = RequestHeader{request, target, version_major: 2, version_minor: 0};
+
+        let mut request = Request{
+            header,
+            headers,
+            data: Vec::new(),
+            body,
+            trailers,
+            client: None
+        };
+
+        if state.boundary.is_some()
+        {
+            PartialRequest::split_multipart(&mut state, &mut request)?;
+        }
+
+        Ok(request)
+    }
+
+    #[allow(dead_code)]
+    pub fn body_text(&self) -> Option<&str>
+    {
+        std::str::from_utf8(&self.body).ok()
+    }
+
+    // fields of an application/x-www-form-urlencoded body
+    pub fn form(&self) -> Option<Vec<(String, String)>>
+    {
+        let content_type = self.headers.content_type()?;
+        if content_type.essence != "application/x-www-form-urlencoded"
+        {
+            return None;
+        }
+
+        parse_urlencoded(std::str::from_utf8(&self.body).ok()?)
+    }
+
+    fn parse_arg(text: &str, separator: char) -> Result<RequestFieldSimple, RequestError>
+    {
+        if let Some(name_split) = text.find(separator)
         {
             let name = text[..name_split].to_owned();
             let body = text[name_split+1..].trim().to_owned();
 
-            let body = if body.starts_with('"') && body.ends_with('"')
+            // a lone " starts and ends with a quote too
+            let body = if body.len() >= 2 && body.starts_with('"') && body.ends_with('"')
             {
-                (&body[1..body.len() - 1]).to_owned()
+                body[1..body.len() - 1].to_owned()
             } else
             {
                 body
@@ -209,9 +685,10 @@ impl Request
     {
         let line_string = String::from_utf8_lossy(line);
 
-        let simple = Self::parse_arg(&line_string)?;
+        let simple = Self::parse_arg(&line_string, ':')?;
 
         let name = simple.name;
+        let value = line_string.split_once(':').map_or("", |(_, value)| value.trim()).to_owned();
 
         let mut bodies = simple.body.split(';').map(|x| x.trim().to_owned());
 
@@ -219,256 +696,735 @@ impl Request
 
         let children = bodies.map(|body|
         {
-            Self::parse_arg(&body)
+            Self::parse_arg(&body, '=')
         }).collect::<Result<Vec<_>, _>>()?;
 
-        if name == "Content-Type"
+        let field = RequestField{this: RequestFieldSimple{name, body}, children, value};
+
+        if field.is("Content-Type")
         {
-            let is_multipart = body.starts_with("multipart");
+            let is_multipart = field.this.body.to_ascii_lowercase().starts_with("multipart");
 
             if is_multipart
             {
-                let inner = children.get(0).ok_or(RequestError::MultipartNoBoundary)?;
-                state.boundary = Some(inner.body.clone());
+                let boundary = field.child("boundary").ok_or(RequestError::MultipartNoBoundary)?;
+                state.boundary = Some(boundary.to_owned());
             }
         }
 
-        Ok(RequestField{this: RequestFieldSimple{name, body}, children})
+        Ok(field)
     }
 
-    fn parse_data<'a>(
+    fn parse_multipart(
         state: &mut RequestState,
-        mut line: &'a [u8]
-    ) -> Result<Option<&'a [u8]>, RequestError>
+        data: &mut Vec<DataPart>,
+        line: &[u8]
+    ) -> Result<(), RequestError>
     {
-        let mut leftover_line = None;
+        if state.is_data_end
+        {
+            return Ok(());
+        }
+
+        let boundary = state.boundary.as_ref().expect("multipart must have a boundary");
+
+        let boundary_end = line.strip_prefix(b"--")
+            .and_then(|line| line.strip_prefix(boundary.as_bytes()))
+            .map(|line| line.trim_ascii())
+            .filter(|line_end| line_end.is_empty() || *line_end == b"--");
+
+        if let Some(line_end) = boundary_end
+        {
+            let is_end = line_end == b"--";
+
+            // the line break before a boundary belongs to the boundary
+            if let Some(last_data) = data.last_mut()
+            {
+                if last_data.data.ends_with(b"\r\n")
+                {
+                    last_data.data.truncate(last_data.data.len() - 2);
+                }
+            }
+
+            if !is_end
+            {
+                data.push(DataPart::new());
+
+                state.is_data_part_header = true;
+            }
+
+            state.is_data_part = !is_end;
+            state.is_data_end = is_end;
 
-        if let Some(leftover) = state.consume_length(line.len())
+            return Ok(());
+        }
+
+        if !state.is_data_part
         {
-            leftover_line = Some(&line[leftover..]);
-            line = &line[..leftover];
+            // preamble, nobody cares
+            return Ok(());
         }
 
+        let last_data = data.last_mut().expect("data part must exist");
+
         if state.is_data_part_header
         {
             if line == b"\r\n"
             {
                 state.is_data_part_header = false;
-                return Ok(leftover_line);
+                return Ok(());
             }
 
+            let line = line.strip_suffix(b"\r\n").unwrap_or(line);
             let parsed = Self::parse_normal(state, line)?;
 
-            state.last_data().fields.push(parsed);
+            last_data.headers.push(parsed);
 
-            return Ok(leftover_line);
+            return Ok(());
         }
 
-        state.last_data().data.extend(line);
+        last_data.data.extend(line);
 
-        Ok(leftover_line)
+        Ok(())
     }
 
-    fn parse_single(
-        state: &mut RequestState,
-        line: &[u8]
-    ) -> Option<Result<RequestField, RequestError>>
+    fn parse_header(state: &mut RequestState, line: &[u8]) -> Result<RequestField, RequestError>
     {
-        if line.starts_with(b"--")
+        state.fields_amount += 1;
+        if line.len() > MAX_HEADER_LINE || state.fields_amount > MAX_HEADER_FIELDS
         {
-            let line = &line[2..];
+            return Err(RequestError::HeaderTooLarge);
+        }
 
-            if let Some(boundary) = &state.boundary
-            {
-                let is_boundary = line.len() >= boundary.len()
-                    && &line[..boundary.len()] == boundary.as_bytes();
+        let parsed = Self::parse_normal(state, line)?;
 
-                if is_boundary
-                {
-                    // i feel like this is overengineering but who cares
-                    let line_end = &line[boundary.len()..];
-                    let is_end = (line_end.len() >= 2)
-                        && &line_end[..2] == b"--";
+        if parsed.is("Content-Length")
+        {
+            let content_length: usize = parsed.this.body.parse()?;
 
-                    if !state.data.is_empty()
-                    {
-                        let last_data = &mut state.last_data().data;
+            if content_length > MAX_BODY_LENGTH
+            {
+                return Err(RequestError::BodyTooLarge(content_length));
+            }
 
-                        if &last_data[last_data.len()-2..] == b"\r\n"
-                        {
-                            last_data.truncate(last_data.len() - 2);
-                        }
-                    }
+            if state.has_length && state.content_length != content_length
+            {
+                return Err(RequestError::AmbiguousLength);
+            }
+
+            state.content_length = content_length;
+            state.has_length = true;
+        }
+
+        if parsed.is("Transfer-Encoding")
+        {
+            let encodings = parsed.value.split(',').map(|x| x.trim().to_ascii_lowercase())
+                .collect::<Vec<_>>();
+
+            if let Some(unsupported) = encodings.iter().find(|x| *x != "chunked")
+            {
+                return Err(RequestError::UnsupportedEncoding(unsupported.clone()));
+            }
+
+            state.is_chunked = !encodings.is_empty();
+        }
+
+        Ok(parsed)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct PartialRequest
+{
+    state: RequestState,
+    request: Option<Request>,
+    buffer: Vec<u8>
+}
+
+impl PartialRequest
+{
+    pub fn new() -> Self
+    {
+        Self::default()
+    }
+
+    // true if theres a request that got started but isnt finished yet
+    pub fn is_pending(&self) -> bool
+    {
+        self.request.is_some() || !self.buffer.is_empty()
+    }
+
+    // feeds more bytes into the parser, returns the request once its fully parsed
+    // any bytes after it stay buffered for the next call
+    pub fn parse(&mut self, s: &[u8]) -> Result<Option<Request>, Error>
+    {
+        self.buffer.extend(s);
+
+        let buffer = std::mem::take(&mut self.buffer);
+        let mut input = &buffer[..];
+
+        let request = self.parse_buffered(&mut input);
 
-                    if !is_end
+        self.buffer = input.to_vec();
+
+        request
+    }
+
+    fn parse_buffered(&mut self, input: &mut &[u8]) -> Result<Option<Request>, Error>
+    {
+        loop
+        {
+            let state = &mut self.state;
+
+            match state.phase
+            {
+                Phase::RequestLine =>
+                {
+                    let line = Self::take_line(input, MAX_TARGET_LENGTH + 64)
+                        .ok_or(RequestError::TargetTooLong)?;
+
+                    let Some(line) = line else { return Ok(None) };
+
+                    // empty lines before a request are allowed
+                    if line.trim_ascii().is_empty()
                     {
-                        state.data.push(DataPart::new());
+                        continue;
+                    }
+
+                    self.request = Some(Self::parse_request_line(line)?);
+                    state.phase = Phase::Headers;
+                },
+                Phase::Headers =>
+                {
+                    let line = Self::take_line(input, MAX_HEADER_LINE)
+                        .ok_or(RequestError::HeaderTooLarge)?;
+
+                    let Some(line) = line else { return Ok(None) };
 
-                        state.is_data_part_header = true;
+                    let line = line.strip_suffix(b"\r\n")
+                        .or_else(|| line.strip_suffix(b"\n"))
+                        .unwrap_or(line);
+
+                    if line.is_empty()
+                    {
+                        state.end_headers()?;
+                        continue;
                     }
 
-                    if is_end
+                    let field = Request::parse_header(state, line)?;
+
+                    self.request.as_mut().expect("request line must be parsed")
+                        .headers.push(field);
+                },
+                Phase::Body =>
+                {
+                    let amount = state.content_length.min(input.len());
+
+                    self.request.as_mut().expect("request line must be parsed")
+                        .body.extend(&input[..amount]);
+
+                    *input = &input[amount..];
+                    state.content_length -= amount;
+
+                    if state.content_length > 0
                     {
-                        state.boundary = None;
+                        return Ok(None);
                     }
 
-                    state.is_data_part = !is_end;
-                    return None;
+                    state.phase = Phase::Done;
+                },
+                Phase::Multipart =>
+                {
+                    let Some(line) = Self::take_multipart_line(state, input) else
+                    {
+                        return Ok(None);
+                    };
+
+                    state.content_length -= line.len();
+
+                    let request = self.request.as_mut().expect("request line must be parsed");
+                    Request::parse_multipart(state, &mut request.data, line)?;
+
+                    if state.content_length == 0
+                    {
+                        state.phase = Phase::Done;
+                    }
+                },
+                Phase::ChunkSize =>
+                {
+                    let line = Self::take_line(input, MAX_HEADER_LINE)
+                        .ok_or(RequestError::MalformedChunk)?;
+
+                    let Some(line) = line else { return Ok(None) };
+
+                    let line = String::from_utf8_lossy(line);
+
+                    // chunk extensions come after a ; and can be ignored
+                    let size = line.split(';').next().expect("split always has a first element")
+                        .trim();
+
+                    let size = usize::from_str_radix(size, 16)
+                        .map_err(|_| RequestError::MalformedChunk)?;
+
+                    state.body_length = state.body_length.checked_add(size)
+                        .ok_or(RequestError::MalformedChunk)?;
+
+                    if state.body_length > MAX_BODY_LENGTH
+                    {
+                        return Err(RequestError::BodyTooLarge(state.body_length).into());
+                    }
+
+                    state.content_length = size;
+                    state.phase = if size == 0 { Phase::Trailers } else { Phase::ChunkData };
+                },
+                Phase::ChunkData =>
+                {
+                    let amount = state.content_length.min(input.len());
+
+                    self.request.as_mut().expect("request line must be parsed")
+                        .body.extend(&input[..amount]);
+
+                    *input = &input[amount..];
+                    state.content_length -= amount;
+
+                    if state.content_length > 0
+                    {
+                        return Ok(None);
+                    }
+
+                    state.phase = Phase::ChunkEnd;
+                },
+                Phase::ChunkEnd =>
+                {
+                    let line = Self::take_line(input, 2).ok_or(RequestError::MalformedChunk)?;
+
+                    let Some(line) = line else { return Ok(None) };
+
+                    if !line.trim_ascii().is_empty()
+                    {
+                        return Err(RequestError::MalformedChunk.into());
+                    }
+
+                    state.phase = Phase::ChunkSize;
+                },
+                Phase::Trailers =>
+                {
+                    let line = Self::take_line(input, MAX_HEADER_LINE)
+                        .ok_or(RequestError::HeaderTooLarge)?;
+
+                    let Some(line) = line else { return Ok(None) };
+
+                    let line = line.trim_ascii_end();
+
+                    let request = self.request.as_mut().expect("request line must be parsed");
+
+                    if line.is_empty()
+                    {
+                        if state.boundary.is_some()
+                        {
+                            Self::split_multipart(state, request)?;
+                        }
+
+                        state.phase = Phase::Done;
+                        continue;
+                    }
+
+                    let field = Request::parse_header(state, line)?;
+                    request.trailers.push(field);
+                },
+                Phase::Done =>
+                {
+                    self.state = RequestState::default();
+
+                    return Ok(self.request.take());
                 }
             }
         }
+    }
+
+    fn split_multipart(state: &mut RequestState, request: &mut Request) -> Result<(), RequestError>
+    {
+        let body = std::mem::take(&mut request.body);
+
+        body.split_inclusive(|c| *c == b'\n').try_for_each(|line|
+        {
+            Request::parse_multipart(state, &mut request.data, line)
+        })
+    }
 
-        if state.is_data_part
+    // returns a full line including the line break, or none if theres no line break yet
+    fn take_line<'a>(input: &mut &'a [u8], limit: usize) -> Option<Option<&'a [u8]>>
+    {
+        match input.iter().position(|c| *c == b'\n')
         {
-            let leftover = match Self::parse_data(state, line)
+            Some(index) =>
             {
-                Ok(x) => x,
-                Err(x) => return Some(Err(x))
-            };
+                let (line, rest) = input.split_at(index + 1);
+                *input = rest;
+
+                Some(Some(line))
+            },
+            None if input.len() > limit => None,
+            None => Some(None)
+        }
+    }
 
-            if let Some(leftover) = leftover
+    fn take_multipart_line<'a>(state: &RequestState, input: &mut &'a [u8]) -> Option<&'a [u8]>
+    {
+        let available = &input[..state.content_length.min(input.len())];
+
+        let boundary_length = state.boundary.as_ref().map_or(0, |x| x.len());
+
+        let length = match available.iter().position(|c| *c == b'\n')
+        {
+            Some(index) => index + 1,
+            None =>
             {
-                return Self::parse_single(state, leftover);
+                // a long enough piece of data without line breaks cant be a boundary
+                let can_split = !state.is_data_part_header
+                    && available.len() > boundary_length + 8;
+
+                let is_last = available.len() == state.content_length;
+
+                if can_split || is_last
+                {
+                    available.len()
+                } else
+                {
+                    return None;
+                }
             }
+        };
 
+        if length == 0
+        {
             return None;
         }
 
-        let line = line.strip_suffix(b"\r\n").unwrap_or(line);
-        if line.is_empty()
+        let (line, rest) = input.split_at(length);
+        *input = rest;
+
+        Some(line)
+    }
+
+    fn parse_request_line(line: &[u8]) -> Result<Request, Error>
+    {
+        let header_fields = String::from_utf8_lossy(line).into_owned();
+
+        let mut header_fields = header_fields.trim_end_matches(['\r', '\n']).split(' ');
+
+        let request_type = header_fields.next().ok_or(RequestError::RequestTypeMissing)?;
+        let request_type = RequestType::parse(request_type)
+            .ok_or_else(|| RequestError::UnknownRequestType(request_type.to_owned()))?;
+
+        let target = header_fields.next().ok_or(RequestError::BodyMissing)?;
+        if target.len() > MAX_TARGET_LENGTH
         {
-            return None;
+            return Err(RequestError::TargetTooLong.into());
         }
 
-        let parsed = match Self::parse_normal(state, line)
+        let target = RequestTarget::parse(target)?;
+
+        let version = header_fields.next().ok_or(RequestError::VersionMissing)?;
+
+        // bytes and not chars, the line went through from_utf8_lossy so anything can be in there
+        let Some(&[major, b'.', minor]) = version.strip_prefix("HTTP/").map(str::as_bytes) else
         {
-            Ok(x) => x,
-            x => return Some(x)
+            return Err(RequestError::MalformedVersion.into());
         };
 
-        if parsed.this.name == "Content-Length"
+        let version_major = (major as char).to_digit(10)
+            .ok_or(RequestError::InvalidMajor)? as u8;
+        if version_major!=1
         {
-            let content_length: usize = match parsed.this.body.parse()
-            {
-                Ok(x) => x,
-                Err(x) => return Some(Err(x.into()))
-            };
-
-            state.content_length = content_length;
+            return Err(RequestError::UnsupportedMajor.into());
         }
 
-        Some(Ok(parsed))
+        let version_minor = (minor as char).to_digit(10)
+            .ok_or(RequestError::InvalidMinor)? as u8;
+
+        let header = RequestHeader{request: request_type, target, version_major, version_minor};
+
+        let request = Request{
+            header,
+            headers: Headers::default(),
+            data: Vec::new(),
+            body: Vec::new(),
+            trailers: Headers::default(),
+            client: None
+        };
+
+        Ok(request)
     }
 }
 
-pub struct PartialRequest
+// name=value pairs separated by &, like in query strings and urlencoded forms
+pub fn parse_urlencoded(s: &str) -> Option<Vec<(String, String)>>
 {
-    pub is_partial: bool,
-    pub request: Request
+    s.split('&').filter(|pair| !pair.is_empty()).map(|pair|
+    {
+        let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+
+        Some((query_decode(name)?, query_decode(value)?))
+    }).collect()
 }
 
-impl PartialRequest
+pub fn percent_decode(s: &str) -> Option<String>
 {
-    pub fn parse(
-        partial: Option<Request>,
-        state: &mut RequestState,
-        s: &[u8]
-    ) -> Result<Self, Error>
-    {
-        let mut lines = s.split_inclusive(|c| *c == b'\n');
+    let mut bytes = s.bytes();
+    let mut decoded = Vec::with_capacity(s.len());
 
-        let mut request = partial.map_or_else(||
+    while let Some(c) = bytes.next()
+    {
+        if c == b'%'
         {
-            *state = RequestState::default();
-
-            Self::parse_non_partial(&mut lines)
-        }, Ok)?;
+            let high = (bytes.next()? as char).to_digit(16)?;
+            let low = (bytes.next()? as char).to_digit(16)?;
 
-        let fields = lines.filter_map(|line|
+            decoded.push((high * 16 + low) as u8);
+        } else
         {
-            Request::parse_single(state, line)
-        }).collect::<Result<Vec<_>, _>>()?;
+            decoded.push(c);
+        }
+    }
 
-        if !state.is_data_part
-        {
-            request.fields.extend(fields.into_iter());
+    String::from_utf8(decoded).ok()
+}
 
-            request.data.append(&mut state.data);
-        }
+// inclusive on both ends, like in the Range header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange
+{
+    pub start: u64,
+    pub end: u64
+}
 
-        let partial = PartialRequest{
-            is_partial: state.is_partial(),
-            request
-        };
+impl ByteRange
+{
+    pub fn length(&self) -> u64
+    {
+        self.end - self.start + 1
+    }
 
-        Ok(partial)
+    pub fn content_range(&self, total: u64) -> String
+    {
+        format!("bytes {}-{}/{total}", self.start, self.end)
     }
+}
 
-    fn parse_non_partial<'a>(mut lines: impl Iterator<Item=&'a [u8]>) -> Result<Request, Error>
+// none means the header should be ignored, an empty list means nothing is satisfiable
+pub fn parse_ranges(value: &str, length: u64) -> Option<Vec<ByteRange>>
+{
+    let specs = value.trim().strip_prefix("bytes=")?
+        .split(',')
+        .map(|spec| spec.trim())
+        .filter(|spec| !spec.is_empty())
+        .collect::<Vec<_>>();
+
+    if specs.is_empty() || specs.len() > MAX_RANGES
     {
-        let header = lines.next().ok_or(RequestError::HeaderMissing)?;
+        return None;
+    }
 
-        let header_fields = String::from_utf8_lossy(header).into_owned();
+    let mut ranges = Vec::new();
+    for spec in specs
+    {
+        let (start, end) = spec.split_once('-')?;
 
-        let mut header_fields = header_fields.strip_suffix("\r\n")
-            .unwrap_or_else(|| &header_fields)
-            .split(' ');
+        if start.is_empty()
+        {
+            let suffix: u64 = end.parse().ok()?;
 
-        let request_type = match header_fields.next().ok_or(RequestError::RequestTypeMissing)?
+            if suffix != 0 && length != 0
+            {
+                ranges.push(ByteRange{start: length.saturating_sub(suffix), end: length - 1});
+            }
+        } else
         {
-            "GET" => Ok(RequestType::Get),
-            "POST" => Ok(RequestType::Post),
-            x => Err(RequestError::UnknownRequestType(x.to_owned()))
-        }?;
+            let start: u64 = start.parse().ok()?;
+            let end: Option<u64> = if end.is_empty() { None } else { Some(end.parse().ok()?) };
 
-        let body = header_fields.next().ok_or(RequestError::BodyMissing)?.to_owned();
+            if end.is_some_and(|end| end < start)
+            {
+                return None;
+            }
 
-        let version = header_fields.next().ok_or(RequestError::VersionMissing)?;
-        if version.len()!=8 || &version[..5]!="HTTP/"
-        {
-            return Err(RequestError::MalformedVersion.into());
-        }
-        let mut version = version[5..].chars();
+            if start < length
+            {
+                let end = end.map_or(length - 1, |end| end.min(length - 1));
 
-        let version_major = version.next().expect("len is 8").to_digit(10)
-            .ok_or(RequestError::InvalidMajor)? as u8;
-        if version_major!=1
-        {
-            return Err(RequestError::UnsupportedMajor.into());
+                ranges.push(ByteRange{start, end});
+            }
         }
+    }
 
-        let version_minor = version.skip(1).next().expect("len is 8").to_digit(10)
-            .ok_or(RequestError::InvalidMinor)? as u8;
+    Some(ranges)
+}
 
-        let header = RequestHeader{request: request_type, body, version_major, version_minor};
+// encodes everything except the unreserved characters
+pub fn percent_encode(s: &str) -> String
+{
+    s.bytes().map(|c|
+    {
+        if c.is_ascii_alphanumeric() || b"-._~".contains(&c)
+        {
+            (c as char).to_string()
+        } else
+        {
+            format!("%{c:02X}")
+        }
+    }).collect()
+}
 
-        let request = Request{header, fields: Vec::new(), data: Vec::new()};
+// encodes every segment of a decoded path but keeps the slashes between them
+pub fn percent_encode_path(s: &str) -> String
+{
+    s.split('/').map(percent_encode).collect::<Vec<_>>().join("/")
+}
 
-        Ok(request)
-    }
+// query strings use + for spaces on top of the usual percent encoding
+pub fn query_decode(s: &str) -> Option<String>
+{
+    percent_decode(&s.replace('+', " "))
 }
 
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Status
 {
+    Continue,
     Ok,
-    NotFound
+    Created,
+    Accepted,
+    NoContent,
+    PartialContent,
+    MovedPermanently,
+    Found,
+    SeeOther,
+    NotModified,
+    TemporaryRedirect,
+    PermanentRedirect,
+    BadRequest,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    MethodNotAllowed,
+    NotAcceptable,
+    RequestTimeout,
+    Conflict,
+    Gone,
+    LengthRequired,
+    PreconditionFailed,
+    PayloadTooLarge,
+    UriTooLong,
+    UnsupportedMediaType,
+    RangeNotSatisfiable,
+    ExpectationFailed,
+    TooManyRequests,
+    HeaderFieldsTooLarge,
+    InternalServerError,
+    NotImplemented,
+    BadGateway,
+    ServiceUnavailable,
+    GatewayTimeout,
+    HttpVersionNotSupported
 }
 
 impl Status
 {
+    pub fn code(&self) -> u16
+    {
+        match self
+        {
+            Status::Continue => 100,
+            Status::Ok => 200,
+            Status::Created => 201,
+            Status::Accepted => 202,
+            Status::NoContent => 204,
+            Status::PartialContent => 206,
+            Status::MovedPermanently => 301,
+            Status::Found => 302,
+            Status::SeeOther => 303,
+            Status::NotModified => 304,
+            Status::TemporaryRedirect => 307,
+            Status::PermanentRedirect => 308,
+            Status::BadRequest => 400,
+            Status::Unauthorized => 401,
+            Status::Forbidden => 403,
+            Status::NotFound => 404,
+            Status::MethodNotAllowed => 405,
+            Status::NotAcceptable => 406,
+            Status::RequestTimeout => 408,
+            Status::Conflict => 409,
+            Status::Gone => 410,
+            Status::LengthRequired => 411,
+            Status::PreconditionFailed => 412,
+            Status::PayloadTooLarge => 413,
+            Status::UriTooLong => 414,
+            Status::UnsupportedMediaType => 415,
+            Status::RangeNotSatisfiable => 416,
+            Status::ExpectationFailed => 417,
+            Status::TooManyRequests => 429,
+            Status::HeaderFieldsTooLarge => 431,
+            Status::InternalServerError => 500,
+            Status::NotImplemented => 501,
+            Status::BadGateway => 502,
+            Status::ServiceUnavailable => 503,
+            Status::GatewayTimeout => 504,
+            Status::HttpVersionNotSupported => 505
+        }
+    }
+
+    pub fn reason(&self) -> &'static str
+    {
+        match self
+        {
+            Status::Continue => "Continue",
+            Status::Ok => "OK",
+            Status::Created => "Created",
+            Status::Accepted => "Accepted",
+            Status::NoContent => "No Content",
+            Status::PartialContent => "Partial Content",
+            Status::MovedPermanently => "Moved Permanently",
+            Status::Found => "Found",
+            Status::SeeOther => "See Other",
+            Status::NotModified => "Not Modified",
+            Status::TemporaryRedirect => "Temporary Redirect",
+            Status::PermanentRedirect => "Permanent Redirect",
+            Status::BadRequest => "Bad Request",
+            Status::Unauthorized => "Unauthorized",
+            Status::Forbidden => "Forbidden",
+            Status::NotFound => "Not Found",
+            Status::MethodNotAllowed => "Method Not Allowed",
+            Status::NotAcceptable => "Not Acceptable",
+            Status::RequestTimeout => "Request Timeout",
+            Status::Conflict => "Conflict",
+            Status::Gone => "Gone",
+            Status::LengthRequired => "Length Required",
+            Status::PreconditionFailed => "Precondition Failed",
+            Status::PayloadTooLarge => "Content Too Large",
+            Status::UriTooLong => "URI Too Long",
+            Status::UnsupportedMediaType => "Unsupported Media Type",
+            Status::RangeNotSatisfiable => "Range Not Satisfiable",
+            Status::ExpectationFailed => "Expectation Failed",
+            Status::TooManyRequests => "Too Many Requests",
+            Status::HeaderFieldsTooLarge => "Request Header Fields Too Large",
+            Status::InternalServerError => "Internal Server Error",
+            Status::NotImplemented => "Not Implemented",
+            Status::BadGateway => "Bad Gateway",
+            Status::ServiceUnavailable => "Service Unavailable",
+            Status::GatewayTimeout => "Gateway Timeout",
+            Status::HttpVersionNotSupported => "HTTP Version Not Supported"
+        }
+    }
+
     pub fn as_bytes(&self) -> Vec<u8>
     {
-        ["HTTP/1.1 ",
-            match self
-            {
-                Status::Ok => "200 OK",
-                Status::NotFound => "404 Not Found"
-            },
-        ].join("").into_bytes()
+        format!("HTTP/1.1 {} {}", self.code(), self.reason()).into_bytes()
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum ContentType
 {
     Html,
@@ -485,7 +1441,8 @@ pub enum ContentType
     Mpeg,
     Ttf,
     Woff,
-    Wasm
+    Wasm,
+    OctetStream
 }
 
 impl ContentType
@@ -509,63 +1466,809 @@ impl ContentType
             "ttf" => ContentType::Ttf,
             "woff" => ContentType::Woff,
             "wasm" => ContentType::Wasm,
-            _ => ContentType::Txt
+            // unknown files could be anything so they dont get treated as text
+            _ => ContentType::OctetStream
         }
     }
 
-    pub fn as_bytes(&self) -> Vec<u8>
+    pub fn as_str(&self) -> &'static str
+    {
+        match self
+        {
+            ContentType::Html => "text/html",
+            ContentType::Javascript => "application/javascript",
+            ContentType::Css => "text/css",
+            ContentType::Png => "image/png",
+            ContentType::Jpg => "image/jpeg",
+            ContentType::Webp => "image/webp",
+            ContentType::Gif => "image/gif",
+            ContentType::Txt => "text/plain",
+            ContentType::Icon => "image/x-icon",
+            ContentType::Json => "application/json",
+            ContentType::Opus => "audio/ogg",
+            ContentType::Mpeg => "audio/mpeg",
+            ContentType::Ttf => "font/ttf",
+            ContentType::Woff => "font/woff",
+            ContentType::Wasm => "application/wasm",
+            ContentType::OctetStream => "application/octet-stream"
+        }
+    }
+}
+
+// writes everything given to it as chunks of a chunked transfer encoded body
+pub struct ChunkedWriter<W: Write>
+{
+    writer: W
+}
+
+impl<W: Write> ChunkedWriter<W>
+{
+    pub fn new(writer: W) -> Self
     {
-        ["Content-Type: ",
-            match self
+        Self{writer}
+    }
+
+    // writes the last empty chunk, the body isnt complete without it
+    pub fn finish(mut self) -> io::Result<W>
+    {
+        self.writer.write_all(b"0\r\n\r\n")?;
+        self.writer.flush()?;
+
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write> Write for ChunkedWriter<W>
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize>
+    {
+        // an empty chunk would end the body early
+        if buf.is_empty()
+        {
+            return Ok(0);
+        }
+
+        let mut chunk = format!("{:x}\r\n", buf.len()).into_bytes();
+        chunk.extend(buf);
+        chunk.extend(b"\r\n");
+
+        self.writer.write_all(&chunk)?;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()>
+    {
+        self.writer.flush()
+    }
+}
+
+pub enum Body
+{
+    Bytes(Vec<u8>),
+    Reader(Box<dyn Read + Send>)
+}
+
+impl fmt::Debug for Body
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match self
+        {
+            Body::Bytes(bytes) => write!(f, "Bytes({} bytes)", bytes.len()),
+            Body::Reader(_) => write!(f, "Reader")
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Response
+{
+    status: Status,
+    headers: Vec<(String, String)>,
+    body: Body,
+    length: Option<u64>,
+    send_body: bool,
+    keep_alive: bool
+}
+
+#[allow(dead_code)]
+impl Response
+{
+    pub fn new(status: Status) -> Self
+    {
+        Self{
+            status,
+            headers: Vec::new(),
+            body: Body::Bytes(Vec::new()),
+            length: None,
+            send_body: true,
+            keep_alive: true
+        }
+    }
+
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self
+    {
+        self.headers.push((name.into(), value.into()));
+
+        self
+    }
+
+    pub fn content_type(self, content_type: ContentType) -> Self
+    {
+        self.header("Content-Type", content_type.as_str())
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self
+    {
+        self.body = Body::Bytes(body.into());
+
+        self
+    }
+
+    // the body gets read and sent in pieces, if theres no length its sent chunked
+    pub fn stream(mut self, reader: impl Read + Send + 'static) -> Self
+    {
+        self.body = Body::Reader(Box::new(reader));
+
+        self
+    }
+
+    // sets the content length without having the body in memory
+    pub fn length(mut self, length: u64) -> Self
+    {
+        self.length = Some(length);
+
+        self
+    }
+
+    // keeps the headers (including the length) but drops the body, for HEAD requests
+    pub fn without_body(mut self) -> Self
+    {
+        self.send_body = false;
+
+        self
+    }
+
+    pub fn close(mut self) -> Self
+    {
+        self.keep_alive = false;
+
+        self
+    }
+
+    pub fn status(&self) -> Status
+    {
+        self.status
+    }
+
+    pub fn headers(&self) -> &[(String, String)]
+    {
+        &self.headers
+    }
+
+    // none if theres nothing to send, like for HEAD requests
+    pub fn into_body(self) -> Option<Body>
+    {
+        self.send_body.then_some(self.body)
+    }
+
+    pub fn get_header(&self, name: &str) -> Option<&str>
+    {
+        self.headers.iter().find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn content_length(&self) -> Option<u64>
+    {
+        match &self.body
+        {
+            Body::Bytes(bytes) => self.length.or(Some(bytes.len() as u64)),
+            Body::Reader(_) => self.length
+        }
+    }
+
+    pub fn head_bytes(&self) -> Vec<u8>
+    {
+        let mut head = self.status.as_bytes();
+        head.extend(b"\r\n");
+
+        let mut add_field = |name: &str, value: &str|
+        {
+            head.extend(format!("{name}: {value}\r\n").as_bytes());
+        };
+
+        add_field("Date", &http_date(SystemTime::now()));
+        add_field("Server", SERVER_NAME);
+
+        self.headers.iter().for_each(|(name, value)| add_field(name, value));
+
+        add_field("Connection", if self.keep_alive { "keep-alive" } else { "close" });
+
+        // these statuses cant have a body at all
+        let has_length = !matches!(
+            self.status,
+            Status::Continue | Status::NoContent | Status::NotModified
+        );
+        if has_length
+        {
+            match self.content_length()
+            {
+                Some(length) => add_field("Content-Length", &length.to_string()),
+                None => add_field("Transfer-Encoding", "chunked")
+            }
+        }
+
+        head.extend(b"\r\n");
+
+        head
+    }
+
+    pub fn write_to(self, mut writer: impl Write) -> io::Result<()>
+    {
+        let length = self.content_length();
+
+        writer.write_all(&self.head_bytes())?;
+
+        if !self.send_body
+        {
+            return writer.flush();
+        }
+
+        match (self.body, length)
+        {
+            (Body::Bytes(bytes), _) => writer.write_all(&bytes)?,
+            (Body::Reader(reader), Some(length)) =>
             {
-                ContentType::Html => "text/html",
-                ContentType::Javascript => "application/javascript",
-                ContentType::Css => "text/css",
-                ContentType::Png => "image/png",
-                ContentType::Jpg => "image/jpeg",
-                ContentType::Webp => "image/webp",
-                ContentType::Gif => "image/gif",
-                ContentType::Txt => "text/plain",
-                ContentType::Icon => "image/x-icon",
-                ContentType::Json => "application/json",
-                ContentType::Opus => "audio/ogg",
-                ContentType::Mpeg => "audio/mpeg",
-                ContentType::Ttf => "font/ttf",
-                ContentType::Woff => "font/woff",
-                ContentType::Wasm => "application/wasm"
+                let copied = copy_chunks(reader.take(length), &mut writer)?;
+
+                // the length was already promised in the header so theres no way to recover
+                if copied != length
+                {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        format!("body ended after {copied} out of {length} bytes")
+                    ));
+                }
             },
-        ].join("").into_bytes()
+            (Body::Reader(reader), None) =>
+            {
+                let mut chunked = ChunkedWriter::new(&mut writer);
+                copy_chunks(reader, &mut chunked)?;
+
+                chunked.finish()?;
+            }
+        }
+
+        writer.flush()
     }
 }
 
-pub fn response(
-    status: Status,
-    content_type: ContentType,
-    data: &[u8]
-) -> Vec<u8>
+// copies in fixed size pieces so memory use doesnt depend on the size of the body
+fn copy_chunks(mut reader: impl Read, mut writer: impl Write) -> io::Result<u64>
 {
-    let mut header = response_header(status, content_type, data.len());
-    header.push(b'\r');
-    header.push(b'\n');
+    let mut buffer = vec![0; STREAM_CHUNK_SIZE];
+    let mut copied = 0;
+
+    loop
+    {
+        let amount = match reader.read(&mut buffer)
+        {
+            Ok(0) => return Ok(copied),
+            Ok(x) => x,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err)
+        };
 
-    header.into_iter().chain(data.iter().cloned()).collect()
+        writer.write_all(&buffer[..amount])?;
+        copied += amount as u64;
+    }
 }
 
-fn response_header(status: Status, content_type: ContentType, length: usize) -> Vec<u8>
+// the epoch was a thursday
+const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun",
+    "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"
+];
+
+pub fn http_date(time: SystemTime) -> String
 {
-    let mut fields: Vec<Vec<u8>> = Vec::new();
+    let seconds = time.duration_since(UNIX_EPOCH).map(|x| x.as_secs()).unwrap_or(0);
+
+    let days = seconds / 86400;
+    let time_of_day = seconds % 86400;
+
+    // civil from days, shifted so years start in march
+    let shifted = days as i64 + 719468;
+    let era = shifted.div_euclid(146097);
+    let day_of_era = shifted - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096)
+        / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{}, {day:02} {} {year} {:02}:{:02}:{:02} GMT",
+        DAYS[(days % 7) as usize],
+        MONTHS[month as usize - 1],
+        time_of_day / 3600,
+        time_of_day % 3600 / 60,
+        time_of_day % 60
+    )
+}
 
-    fields.push(status.as_bytes());
-    fields.push(content_type.as_bytes());
-    fields.push(b"Connection: keep-alive".to_vec());
+// only understands the IMF-fixdate format that everything sends nowadays
+pub fn parse_http_date(s: &str) -> Option<SystemTime>
+{
+    let mut fields = s.trim().split(' ');
 
-    if length!=0
+    let weekday = fields.next()?.strip_suffix(',')?;
+    if !DAYS.contains(&weekday)
     {
-        fields.push(format!("Content-Length: {length}").into_bytes());
+        return None;
     }
 
-    fields.into_iter().flat_map(|field|
+    let day: i64 = fields.next()?.parse().ok()?;
+    let month = fields.next()?;
+    let month = MONTHS.iter().position(|x| *x == month)? as i64 + 1;
+    let year: i64 = fields.next()?.parse().ok()?;
+
+    let mut time = fields.next()?.split(':').map(|x| x.parse::<u64>().ok());
+    let (hours, minutes, seconds) = (time.next()??, time.next()??, time.next()??);
+
+    // four digit years are all the format allows and nothing before the epoch matters
+    if fields.next()? != "GMT" || !(1..=31).contains(&day) || !(1970..=9999).contains(&year)
+        || hours > 23 || minutes > 59 || seconds > 60
     {
-        field.into_iter().chain(b"\r\n".iter().cloned())
-    }).collect()
+        return None;
+    }
+
+    // days from civil, the inverse of http_date
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let shifted_month = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * shifted_month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    let days = u64::try_from(era * 146097 + day_of_era - 719468).ok()?;
+
+    let seconds = days.checked_mul(86400)?
+        .checked_add(hours * 3600 + minutes * 60 + seconds)?;
+
+    UNIX_EPOCH.checked_add(std::time::Duration::from_secs(seconds))
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::time::Duration;
+
+    use super::*;
+
+
+    fn parse(input: &[u8]) -> Result<Option<Request>, Error>
+    {
+        PartialRequest::new().parse(input)
+    }
+
+    // feeds the input in pieces of every size, the result has to be the same each time
+    fn parse_split(input: &[u8]) -> Vec<Request>
+    {
+        (1..=input.len()).map(|step|
+        {
+            let mut parser = PartialRequest::new();
+
+            let requests = input.chunks(step).filter_map(|piece|
+            {
+                parser.parse(piece).unwrap()
+            }).collect::<Vec<_>>();
+
+            assert!(!parser.is_pending(), "step {step}");
+            assert_eq!(requests.len(), 1, "step {step}");
+
+            requests.into_iter().next().unwrap()
+        }).collect()
+    }
+
+    #[test]
+    fn parses_bodies_by_length()
+    {
+        let input = b"POST /a HTTP/1.1\r\nContent-Length: 12\r\n\r\nhello\r\nworld";
+
+        parse_split(input).into_iter().for_each(|request|
+        {
+            assert_eq!(request.body, b"hello\r\nworld");
+            assert_eq!(request.body_text(), Some("hello\r\nworld"));
+        });
+
+        // whatever comes after the body is the next request
+        let mut parser = PartialRequest::new();
+        let request = parser.parse(b"POST / HTTP/1.1\r\nContent-Length: 2\r\n\r\nabGET").unwrap();
+        assert_eq!(request.unwrap().body, b"ab");
+        assert!(parser.is_pending());
+
+        let request = parse(b"POST / HTTP/1.1\r\nContent-Length: 0\r\n\r\n").unwrap().unwrap();
+        assert!(request.body.is_empty());
+
+        assert!(parse(b"POST / HTTP/1.1\r\nContent-Length: x\r\n\r\n").is_err());
+
+        let length = MAX_BODY_LENGTH + 1;
+        let too_long = format!("POST / HTTP/1.1\r\nContent-Length: {length}\r\n\r\n");
+        assert!(matches!(
+            parse(too_long.as_bytes()),
+            Err(Error::Request(RequestError::BodyTooLarge(_)))
+        ));
+    }
+
+    #[test]
+    fn parses_urlencoded_forms()
+    {
+        let input = b"POST / HTTP/1.1\r\nContent-Type: application/x-www-form-urlencoded\r\n\
+            Content-Length: 37\r\n\r\ntext_message=a+b%21&empty=&flag&x=%3D";
+
+        parse_split(input).into_iter().for_each(|request|
+        {
+            assert_eq!(request.form().unwrap(), [
+                ("text_message".to_owned(), "a b!".to_owned()),
+                ("empty".to_owned(), String::new()),
+                ("flag".to_owned(), String::new()),
+                ("x".to_owned(), "=".to_owned())
+            ]);
+        });
+
+        let plain = parse(b"POST / HTTP/1.1\r\nContent-Length: 3\r\n\r\na=b").unwrap().unwrap();
+        assert!(plain.form().is_none());
+
+        assert_eq!(parse_urlencoded("a=%zz"), None);
+        assert_eq!(parse_urlencoded(""), Some(Vec::new()));
+    }
+
+    #[test]
+    fn parses_multipart_bodies()
+    {
+        let body = "--b\r\nContent-Disposition: form-data; name=\"text_message\"\r\n\r\n\
+            hi there\r\n--b\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\
+            Content-Type: text/plain\r\n\r\nline one\r\nline two\r\n--b--\r\n";
+
+        let input = format!(
+            "POST / HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=b\r\n\
+            Content-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+
+        parse_split(input.as_bytes()).into_iter().for_each(|request|
+        {
+            let parts = request.data.iter().filter(|part| !part.data.is_empty())
+                .collect::<Vec<_>>();
+
+            assert_eq!(parts.len(), 2);
+
+            let disposition = parts[0].headers.get("Content-Disposition").unwrap();
+            assert_eq!(disposition.child("name"), Some("text_message"));
+            assert_eq!(parts[0].data, b"hi there");
+
+            let disposition = parts[1].headers.get("Content-Disposition").unwrap();
+            assert_eq!(disposition.child("filename"), Some("a.txt"));
+            assert_eq!(parts[1].data, b"line one\r\nline two");
+        });
+
+        let empty = b"POST / HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=b\r\n\
+            Content-Length: 0\r\n\r\n";
+
+        let mut parser = PartialRequest::new();
+        assert!(parser.parse(empty).unwrap().is_some());
+        assert!(!parser.is_pending());
+
+        let no_length = b"POST / HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=b\r\n\r\n";
+        assert!(matches!(parse(no_length), Err(Error::Request(RequestError::LengthRequired))));
+
+        let no_boundary = b"POST / HTTP/1.1\r\nContent-Type: multipart/form-data\r\n\
+            Content-Length: 0\r\n\r\n";
+        assert!(parse(no_boundary).is_err());
+    }
+
+    #[test]
+    fn parses_chunked_bodies()
+    {
+        let input = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+            5;name=value\r\nhello\r\nA\r\n0123456789\r\n0\r\nX-Trailer: yes\r\n\r\n";
+
+        parse_split(input).into_iter().for_each(|request|
+        {
+            assert_eq!(request.body, b"hello0123456789");
+
+            let trailer = request.trailers.iter().find(|field| field.is("x-trailer")).unwrap();
+            assert_eq!(trailer.value, "yes");
+        });
+
+        let empty = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n";
+        assert!(parse(empty).unwrap().unwrap().body.is_empty());
+
+        // the chunked body ends right where the next request starts
+        let mut parser = PartialRequest::new();
+        let input = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n1\r\na\r\n0\r\n\r\nGET";
+        assert_eq!(parser.parse(input).unwrap().unwrap().body, b"a");
+        assert!(parser.is_pending());
+    }
+
+    #[test]
+    fn rejects_malformed_versions()
+    {
+        let version_error = |version: &[u8]|
+        {
+            let mut input = b"GET / ".to_vec();
+            input.extend(version);
+            input.extend(b"\r\n\r\n");
+
+            match parse(&input)
+            {
+                Err(Error::Request(err)) => Some((err.status(), err)),
+                _ => None
+            }
+        };
+
+        let is_malformed = |version: &[u8]|
+        {
+            matches!(
+                version_error(version),
+                Some((Status::BadRequest, RequestError::MalformedVersion))
+            )
+        };
+
+        // multibyte characters where the slashes and dots would be sliced
+        assert!(is_malformed(b"abcd\xffb"));
+        assert!(is_malformed(b"HTTP/1\xff"));
+        assert!(is_malformed("HTTP/1é".as_bytes()));
+        assert!(is_malformed("HTTP/é1".as_bytes()));
+        assert!(is_malformed("HTTPé/1.1".as_bytes()));
+        assert!(is_malformed(b"HTTP/1x1"));
+        assert!(is_malformed(b"HTTP/1.10"));
+        assert!(is_malformed(b"http/1.1"));
+        assert!(is_malformed(b"HTTP/"));
+
+        assert!(matches!(version_error(b"HTTP/x.1"), Some((_, RequestError::InvalidMajor))));
+        assert!(matches!(version_error(b"HTTP/1.x"), Some((_, RequestError::InvalidMinor))));
+        assert!(matches!(
+            version_error(b"HTTP/2.0"),
+            Some((Status::HttpVersionNotSupported, RequestError::UnsupportedMajor))
+        ));
+        assert!(matches!(
+            parse(b"GET /\r\n\r\n"),
+            Err(Error::Request(RequestError::VersionMissing))
+        ));
+
+        let request = parse(b"GET / HTTP/1.0\r\n\r\n").unwrap().unwrap();
+        assert_eq!((request.header.version_major, request.header.version_minor), (1, 0));
+    }
+
+    #[test]
+    fn rejects_ambiguous_lengths()
+    {
+        let is_ambiguous = |fields: &str|
+        {
+            let input = format!("POST / HTTP/1.1\r\n{fields}\r\n0\r\n\r\n");
+
+            match parse(input.as_bytes())
+            {
+                Err(Error::Request(err)) =>
+                {
+                    assert_eq!(err.status(), Status::BadRequest);
+                    matches!(err, RequestError::AmbiguousLength)
+                },
+                _ => false
+            }
+        };
+
+        assert!(is_ambiguous("Transfer-Encoding: chunked\r\nContent-Length: 3\r\n"));
+        assert!(is_ambiguous("Content-Length: 3\r\nTransfer-Encoding: chunked\r\n"));
+        assert!(is_ambiguous("Content-Length: 0\r\nContent-Length: 5\r\n"));
+        assert!(is_ambiguous("Content-Length: 5\r\ncontent-length: 0\r\n"));
+
+        // the same length twice still says the same thing
+        let input = b"POST / HTTP/1.1\r\nContent-Length: 2\r\nContent-Length: 2\r\n\r\nhi";
+        assert_eq!(parse(input).unwrap().unwrap().body, b"hi");
+    }
+
+    #[test]
+    fn rejects_malformed_chunks()
+    {
+        let chunked = |body: &str|
+        {
+            let input = format!("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n{body}");
+
+            parse(input.as_bytes())
+        };
+
+        let is_malformed = |result: Result<Option<Request>, Error>|
+        {
+            matches!(result, Err(Error::Request(RequestError::MalformedChunk)))
+        };
+
+        assert!(is_malformed(chunked("zz\r\n")));
+        assert!(is_malformed(chunked("-1\r\n")));
+        assert!(is_malformed(chunked("\r\n")));
+        assert!(is_malformed(chunked("ffffffffffffffffffff\r\n")));
+        assert!(is_malformed(chunked("5\r\nhelloXX\r\n0\r\n\r\n")));
+
+        // no line break in sight after way more than a size line could be
+        assert!(is_malformed(chunked(&"1".repeat(MAX_HEADER_LINE + 1))));
+
+        let is_too_large = |result: Result<Option<Request>, Error>|
+        {
+            matches!(result, Err(Error::Request(RequestError::BodyTooLarge(_))))
+        };
+
+        assert!(is_too_large(chunked(&format!("{:x}\r\n", MAX_BODY_LENGTH + 1))));
+
+        let half = MAX_BODY_LENGTH / 2 + 1;
+        let mut parser = PartialRequest::new();
+        let head = format!("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n{half:x}\r\n");
+        assert!(parser.parse(head.as_bytes()).unwrap().is_none());
+        assert!(parser.parse(&vec![b'a'; half]).unwrap().is_none());
+        assert!(is_too_large(parser.parse(format!("\r\n{half:x}\r\n").as_bytes())));
+
+        // one huge trailer line is just as bad as a huge header
+        let trailer = format!("0\r\nX-Big: {}", "a".repeat(MAX_HEADER_LINE + 1));
+        assert!(matches!(
+            chunked(&trailer),
+            Err(Error::Request(RequestError::HeaderTooLarge))
+        ));
+
+        let gzip = b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n";
+        assert!(matches!(
+            parse(gzip),
+            Err(Error::Request(RequestError::UnsupportedEncoding(_)))
+        ));
+    }
+
+    #[test]
+    fn splits_chunked_multipart()
+    {
+        let body = "--b\r\nContent-Disposition: form-data; name=\"text_message\"\r\n\r\n\
+            split over chunks\r\n--b--\r\n";
+
+        let (first, second) = body.split_at(30);
+
+        let input = format!(
+            "POST / HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=b\r\n\
+            Transfer-Encoding: chunked\r\n\r\n{:x}\r\n{first}\r\n{:x}\r\n{second}\r\n0\r\n\r\n",
+            first.len(),
+            second.len()
+        );
+
+        parse_split(input.as_bytes()).into_iter().for_each(|request|
+        {
+            let part = request.data.iter().find(|part| !part.data.is_empty()).unwrap();
+
+            let disposition = part.headers.get("Content-Disposition").unwrap();
+            assert_eq!(disposition.child("name"), Some("text_message"));
+            assert_eq!(part.data, b"split over chunks");
+        });
+    }
+
+    #[test]
+    fn writes_chunks()
+    {
+        let mut writer = ChunkedWriter::new(Vec::new());
+
+        writer.write_all(b"abc").unwrap();
+        writer.write_all(b"").unwrap();
+        writer.write_all(&[b'x'; 26]).unwrap();
+
+        let output = writer.finish().unwrap();
+
+        let expected = format!("3\r\nabc\r\n1a\r\n{}\r\n0\r\n\r\n", "x".repeat(26));
+        assert_eq!(output, expected.as_bytes());
+
+        // whatever gets written has to come back out of the parser the same
+        let mut input = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+        input.extend(output);
+
+        parse_split(&input).into_iter().for_each(|request|
+        {
+            assert_eq!(request.body, [b"abc".as_slice(), &[b'x'; 26]].concat());
+        });
+    }
+
+    #[test]
+    fn parses_byte_ranges()
+    {
+        let ranges = |value: &str, length: u64|
+        {
+            parse_ranges(value, length).map(|ranges|
+            {
+                ranges.into_iter().map(|range| (range.start, range.end)).collect::<Vec<_>>()
+            })
+        };
+
+        assert_eq!(ranges("bytes=0-4", 10), Some(vec![(0, 4)]));
+        assert_eq!(ranges("bytes=2-100", 10), Some(vec![(2, 9)]));
+
+        // open ended and suffix ranges
+        assert_eq!(ranges("bytes=5-", 10), Some(vec![(5, 9)]));
+        assert_eq!(ranges("bytes=-3", 10), Some(vec![(7, 9)]));
+        assert_eq!(ranges("bytes=-30", 10), Some(vec![(0, 9)]));
+
+        assert_eq!(ranges(" bytes=0-0, -1 ,, 4-5", 10), Some(vec![(0, 0), (9, 9), (4, 5)]));
+
+        // nothing satisfiable
+        assert_eq!(ranges("bytes=10-", 10), Some(vec![]));
+        assert_eq!(ranges("bytes=-0", 10), Some(vec![]));
+        assert_eq!(ranges("bytes=0-", 0), Some(vec![]));
+        assert_eq!(ranges("bytes=-5", 0), Some(vec![]));
+
+        // malformed ones get ignored completely
+        assert_eq!(ranges("bytes=5-2", 10), None);
+        assert_eq!(ranges("bytes=a-b", 10), None);
+        assert_eq!(ranges("bytes=5", 10), None);
+        assert_eq!(ranges("bytes=", 10), None);
+        assert_eq!(ranges("items=0-1", 10), None);
+        assert_eq!(ranges("bytes=--1", 10), None);
+
+        let many = format!("bytes={}", vec!["0-0"; MAX_RANGES + 1].join(","));
+        assert_eq!(ranges(&many, 10), None);
+
+        let range = ByteRange{start: 2, end: 5};
+        assert_eq!(range.length(), 4);
+        assert_eq!(range.content_range(10), "bytes 2-5/10");
+    }
+
+    #[test]
+    fn parses_quoted_header_values()
+    {
+        let input = b"GET / HTTP/1.1\r\nX-Foo: \"\r\nX-Bar: a; c=\"\r\n\r\n";
+        let request = parse(input).unwrap().unwrap();
+
+        assert_eq!(request.headers.get("X-Foo").unwrap().this.body, "\"");
+
+        let bar = request.headers.get("X-Bar").unwrap();
+        assert_eq!(bar.this.body, "a");
+        assert_eq!(bar.child("c"), Some("\""));
+
+        let fields = [("x-foo".to_owned(), "\"".to_owned())];
+        assert!(Request::from_parts("GET", "/", &fields, Vec::new(), &[]).is_ok());
+    }
+
+    #[test]
+    fn round_trips_http_dates()
+    {
+        let dates = [
+            (0, "Thu, 01 Jan 1970 00:00:00 GMT"),
+            (784111777, "Sun, 06 Nov 1994 08:49:37 GMT"),
+            (951782400, "Tue, 29 Feb 2000 00:00:00 GMT"),
+            (253402300799, "Fri, 31 Dec 9999 23:59:59 GMT")
+        ];
+
+        dates.into_iter().for_each(|(seconds, text)|
+        {
+            let time = UNIX_EPOCH + Duration::from_secs(seconds);
+
+            assert_eq!(http_date(time), text);
+            assert_eq!(parse_http_date(text), Some(time));
+        });
+    }
+
+    #[test]
+    fn rejects_bad_http_dates()
+    {
+        [
+            "Thu, 01 Jan 300000000000 00:00:00 GMT",
+            "Thu, 01 Jan 9223372036854775807 00:00:00 GMT",
+            "Thu, 01 Jan 10000 00:00:00 GMT",
+            "Wed, 31 Dec 1969 23:59:59 GMT",
+            "Thu, 01 Jan -1 00:00:00 GMT",
+            "Thu, 32 Jan 2000 00:00:00 GMT",
+            "Thu, 01 Foo 2000 00:00:00 GMT",
+            "Thu, 01 Jan 2000 24:00:00 GMT",
+            "Thu, 01 Jan 2000 00:00:00 UTC",
+            "Sunday, 06-Nov-94 08:49:37 GMT",
+            ""
+        ].into_iter().for_each(|text| assert_eq!(parse_http_date(text), None, "{text}"));
+    }
 }
@@ -0,0 +1,1283 @@
+use std::{
+    io::{self, Read, Write, Cursor},
+    collections::HashMap,
+    time::SystemTime
+};
+
+use super::{
+    hpack,
+    http::{
+        self,
+        Body,
+        Request,
+        Response,
+        Status,
+        SERVER_NAME,
+        MAX_BODY_LENGTH,
+        http_date
+    }
+};
+
+
+pub const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+// what every connection and stream starts with before any settings
+const DEFAULT_WINDOW: i64 = 65535;
+const DEFAULT_FRAME_SIZE: usize = 16384;
+
+const MAX_WINDOW: i64 = (1 << 31) - 1;
+const MAX_FRAME_SIZE: usize = (1 << 24) - 1;
+
+const MAX_CONCURRENT_STREAMS: u32 = 100;
+
+// a header block split over continuations cant grow past this
+const MAX_HEADER_BLOCK: usize = 64 * 1024;
+
+// request bodies all streams together can have waiting in memory, one full body still fits
+const MAX_BUFFERED: usize = MAX_BODY_LENGTH + DEFAULT_WINDOW as usize;
+
+const FRAME_HEADER_LENGTH: usize = 9;
+
+// these only mean something for a single http1 connection so theyre not allowed in http2
+const CONNECTION_HEADERS: [&str; 5] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade"
+];
+
+const FLAG_END_STREAM: u8 = 0x1;
+const FLAG_ACK: u8 = 0x1;
+const FLAG_END_HEADERS: u8 = 0x4;
+const FLAG_PADDED: u8 = 0x8;
+const FLAG_PRIORITY: u8 = 0x20;
+
+const SETTINGS_HEADER_TABLE_SIZE: u16 = 0x1;
+const SETTINGS_ENABLE_PUSH: u16 = 0x2;
+const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FrameType
+{
+    Data,
+    Headers,
+    Priority,
+    RstStream,
+    Settings,
+    PushPromise,
+    Ping,
+    GoAway,
+    WindowUpdate,
+    Continuation,
+    Unknown(u8)
+}
+
+impl FrameType
+{
+    fn parse(value: u8) -> Self
+    {
+        match value
+        {
+            0x0 => FrameType::Data,
+            0x1 => FrameType::Headers,
+            0x2 => FrameType::Priority,
+            0x3 => FrameType::RstStream,
+            0x4 => FrameType::Settings,
+            0x5 => FrameType::PushPromise,
+            0x6 => FrameType::Ping,
+            0x7 => FrameType::GoAway,
+            0x8 => FrameType::WindowUpdate,
+            0x9 => FrameType::Continuation,
+            x => FrameType::Unknown(x)
+        }
+    }
+
+    fn as_byte(&self) -> u8
+    {
+        match self
+        {
+            FrameType::Data => 0x0,
+            FrameType::Headers => 0x1,
+            FrameType::Priority => 0x2,
+            FrameType::RstStream => 0x3,
+            FrameType::Settings => 0x4,
+            FrameType::PushPromise => 0x5,
+            FrameType::Ping => 0x6,
+            FrameType::GoAway => 0x7,
+            FrameType::WindowUpdate => 0x8,
+            FrameType::Continuation => 0x9,
+            FrameType::Unknown(x) => *x
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ErrorCode
+{
+    NoError,
+    ProtocolError,
+    InternalError,
+    FlowControlError,
+    StreamClosed,
+    FrameSizeError,
+    RefusedStream,
+    CompressionError,
+    EnhanceYourCalm
+}
+
+impl ErrorCode
+{
+    fn code(&self) -> u32
+    {
+        match self
+        {
+            ErrorCode::NoError => 0x0,
+            ErrorCode::ProtocolError => 0x1,
+            ErrorCode::InternalError => 0x2,
+            ErrorCode::FlowControlError => 0x3,
+            ErrorCode::StreamClosed => 0x5,
+            ErrorCode::FrameSizeError => 0x6,
+            ErrorCode::RefusedStream => 0x7,
+            ErrorCode::CompressionError => 0x9,
+            ErrorCode::EnhanceYourCalm => 0xb
+        }
+    }
+}
+
+// connection errors end everything, stream errors only reset that one stream
+#[derive(Debug)]
+enum FrameError
+{
+    Connection(ErrorCode),
+    Stream(u32, ErrorCode),
+    Io(io::Error)
+}
+
+impl From<io::Error> for FrameError
+{
+    fn from(value: io::Error) -> Self
+    {
+        FrameError::Io(value)
+    }
+}
+
+// a response body thats still waiting for flow control to let it through
+struct PendingBody
+{
+    reader: Box<dyn Read + Send>,
+    remaining: Option<u64>
+}
+
+impl PendingBody
+{
+    // up to limit bytes and whether thats the end of the body
+    fn next_chunk(&mut self, limit: usize) -> io::Result<(Vec<u8>, bool)>
+    {
+        let limit = self.remaining.map_or(limit as u64, |remaining| remaining.min(limit as u64));
+
+        let mut chunk = Vec::new();
+        self.reader.by_ref().take(limit).read_to_end(&mut chunk)?;
+
+        let is_done = match &mut self.remaining
+        {
+            Some(remaining) =>
+            {
+                *remaining -= chunk.len() as u64;
+
+                if *remaining > 0 && (chunk.len() as u64) < limit
+                {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        format!("body ended {remaining} bytes early")
+                    ));
+                }
+
+                *remaining == 0
+            },
+            None => (chunk.len() as u64) < limit
+        };
+
+        Ok((chunk, is_done))
+    }
+}
+
+struct Stream
+{
+    fields: Vec<(String, String)>,
+    trailers: Vec<(String, String)>,
+    body: Vec<u8>,
+    send_window: i64,
+    receive_window: i64,
+    // the client sent everything it had
+    remote_closed: bool,
+    // the body got too big and was already answered, the rest gets thrown away
+    discarding: bool,
+    outgoing: Option<PendingBody>
+}
+
+impl Stream
+{
+    fn new(fields: Vec<(String, String)>, send_window: i64) -> Self
+    {
+        Self{
+            fields,
+            trailers: Vec::new(),
+            body: Vec::new(),
+            send_window,
+            receive_window: DEFAULT_WINDOW,
+            remote_closed: false,
+            discarding: false,
+            outgoing: None
+        }
+    }
+}
+
+// a header block that continues in continuation frames
+struct PartialBlock
+{
+    stream: u32,
+    end_stream: bool,
+    block: Vec<u8>
+}
+
+pub type StreamRequest = (u32, Result<Request, http::Error>);
+
+// the framing side of an http2 connection, requests come out and responses go in
+pub struct Connection
+{
+    buffer: Vec<u8>,
+    preface_received: bool,
+    settings_sent: bool,
+    decoder: hpack::Decoder,
+    streams: HashMap<u32, Stream>,
+    last_stream: u32,
+    continuation: Option<PartialBlock>,
+    send_window: i64,
+    receive_window: i64,
+    // the window new streams start with, the client picks it
+    initial_window: i64,
+    max_frame_size: usize,
+    peer_going_away: bool,
+    closed: bool,
+    // follows the listeners log_connections switch
+    log_errors: bool
+}
+
+impl Connection
+{
+    pub fn new(log_errors: bool) -> Self
+    {
+        Self{
+            buffer: Vec::new(),
+            preface_received: false,
+            settings_sent: false,
+            decoder: hpack::Decoder::new(),
+            streams: HashMap::new(),
+            last_stream: 0,
+            continuation: None,
+            send_window: DEFAULT_WINDOW,
+            receive_window: DEFAULT_WINDOW,
+            initial_window: DEFAULT_WINDOW,
+            max_frame_size: DEFAULT_FRAME_SIZE,
+            peer_going_away: false,
+            closed: false,
+            log_errors
+        }
+    }
+
+    // true once theres nothing left to do on this connection
+    pub fn is_closed(&self) -> bool
+    {
+        self.closed || (self.peer_going_away && self.streams.is_empty())
+    }
+
+    // feeds more bytes in, control frames get answered right away
+    // and the requests that got finished come out
+    pub fn receive(
+        &mut self,
+        input: &[u8],
+        mut writer: impl Write
+    ) -> io::Result<Vec<StreamRequest>>
+    {
+        self.send_settings(&mut writer)?;
+
+        self.buffer.extend(input);
+
+        if !self.preface_received
+        {
+            if self.buffer.len() < PREFACE.len()
+            {
+                return Ok(Vec::new());
+            }
+
+            if !self.buffer.starts_with(PREFACE)
+            {
+                self.go_away(ErrorCode::ProtocolError, &mut writer)?;
+
+                return Ok(Vec::new());
+            }
+
+            self.buffer.drain(..PREFACE.len());
+            self.preface_received = true;
+        }
+
+        let mut requests = Vec::new();
+
+        let buffer = std::mem::take(&mut self.buffer);
+        let mut input = &buffer[..];
+
+        while !self.closed && input.len() >= FRAME_HEADER_LENGTH
+        {
+            let length = u32::from_be_bytes([0, input[0], input[1], input[2]]) as usize;
+            let kind = FrameType::parse(input[3]);
+            let flags = input[4];
+            let stream = u32::from_be_bytes([input[5], input[6], input[7], input[8]]) & 0x7fff_ffff;
+
+            // the only frame size the client is allowed to go up to is the default one
+            if length > DEFAULT_FRAME_SIZE
+            {
+                self.go_away(ErrorCode::FrameSizeError, &mut writer)?;
+                break;
+            }
+
+            if input.len() < FRAME_HEADER_LENGTH + length
+            {
+                break;
+            }
+
+            let payload = &input[FRAME_HEADER_LENGTH..FRAME_HEADER_LENGTH + length];
+            input = &input[FRAME_HEADER_LENGTH + length..];
+
+            match self.frame(kind, flags, stream, payload, &mut writer, &mut requests)
+            {
+                Ok(()) => (),
+                Err(FrameError::Stream(stream, code)) => self.reset(stream, code, &mut writer)?,
+                Err(FrameError::Connection(code)) => self.go_away(code, &mut writer)?,
+                Err(FrameError::Io(err)) => return Err(err)
+            }
+        }
+
+        self.buffer = input.to_vec();
+
+        if !self.closed
+        {
+            self.grant_receive_window(&mut writer)?;
+        }
+
+        writer.flush()?;
+
+        Ok(requests)
+    }
+
+    // lets the client know no more streams will be taken, like when it stayed quiet for too long
+    pub fn close(&mut self, writer: impl Write) -> io::Result<()>
+    {
+        self.go_away(ErrorCode::NoError, writer)
+    }
+
+    pub fn send_response(
+        &mut self,
+        stream: u32,
+        response: Response,
+        mut writer: impl Write
+    ) -> io::Result<()>
+    {
+        // the client could have reset it already
+        if !self.streams.contains_key(&stream)
+        {
+            return Ok(());
+        }
+
+        let status = response.status();
+
+        let mut fields = vec![
+            (":status".to_owned(), status.code().to_string()),
+            ("date".to_owned(), http_date(SystemTime::now())),
+            ("server".to_owned(), SERVER_NAME.to_owned())
+        ];
+
+        // clients reject responses with connection headers in them
+        fields.extend(response.headers().iter().map(|(name, value)|
+        {
+            (name.to_ascii_lowercase(), value.clone())
+        }).filter(|(name, _)| !CONNECTION_HEADERS.contains(&name.as_str())));
+
+        let has_body = !matches!(
+            status,
+            Status::Continue | Status::NoContent | Status::NotModified
+        );
+
+        let length = response.content_length();
+        if let Some(length) = length.filter(|_| has_body)
+        {
+            fields.push(("content-length".to_owned(), length.to_string()));
+        }
+
+        let body = match response.into_body().filter(|_| has_body)
+        {
+            Some(Body::Bytes(bytes)) if !bytes.is_empty() =>
+            {
+                let remaining = Some(bytes.len() as u64);
+
+                Some(PendingBody{reader: Box::new(Cursor::new(bytes)), remaining})
+            },
+            Some(Body::Reader(reader)) => Some(PendingBody{reader, remaining: length}),
+            _ => None
+        };
+
+        self.write_headers(stream, &hpack::encode(&fields), body.is_none(), &mut writer)?;
+
+        match body
+        {
+            Some(body) =>
+            {
+                let stream = self.streams.get_mut(&stream).expect("checked at the start");
+                stream.outgoing = Some(body);
+            },
+            None => self.finish(stream, &mut writer)?
+        }
+
+        Ok(())
+    }
+
+    // sends as much of the response bodies as flow control allows, a bit of each at a time
+    pub fn send_pending(&mut self, mut writer: impl Write) -> io::Result<()>
+    {
+        loop
+        {
+            let mut ids = self.streams.iter().filter(|(_, stream)| stream.outgoing.is_some())
+                .map(|(id, _)| *id)
+                .collect::<Vec<_>>();
+
+            ids.sort_unstable();
+
+            let mut progressed = false;
+
+            for id in ids
+            {
+                let stream = self.streams.get_mut(&id).expect("ids come from the map");
+
+                let limit = self.send_window.min(stream.send_window)
+                    .min(self.max_frame_size as i64);
+
+                if limit <= 0
+                {
+                    continue;
+                }
+
+                let body = stream.outgoing.as_mut().expect("filtered by outgoing");
+
+                let (chunk, is_done) = match body.next_chunk(limit as usize)
+                {
+                    Ok(x) => x,
+                    Err(err) =>
+                    {
+                        if self.log_errors
+                        {
+                            println!("error sending response body ({err})");
+                        }
+
+                        self.reset(id, ErrorCode::InternalError, &mut writer)?;
+                        continue;
+                    }
+                };
+
+                stream.send_window -= chunk.len() as i64;
+                self.send_window -= chunk.len() as i64;
+
+                let flags = if is_done { FLAG_END_STREAM } else { 0 };
+                write_frame(&mut writer, FrameType::Data, flags, id, &chunk)?;
+
+                if is_done
+                {
+                    stream.outgoing = None;
+                    self.finish(id, &mut writer)?;
+                }
+
+                progressed = true;
+            }
+
+            if !progressed
+            {
+                break;
+            }
+        }
+
+        writer.flush()
+    }
+
+    fn frame(
+        &mut self,
+        kind: FrameType,
+        flags: u8,
+        stream: u32,
+        payload: &[u8],
+        writer: &mut impl Write,
+        requests: &mut Vec<StreamRequest>
+    ) -> Result<(), FrameError>
+    {
+        // nothing can come between a header block and its continuations
+        if self.continuation.is_some() && kind != FrameType::Continuation
+        {
+            return Err(FrameError::Connection(ErrorCode::ProtocolError));
+        }
+
+        let is_connection_frame = matches!(
+            kind,
+            FrameType::Settings | FrameType::Ping | FrameType::GoAway
+        );
+
+        let is_stream_frame = matches!(
+            kind,
+            FrameType::Data
+                | FrameType::Headers
+                | FrameType::Priority
+                | FrameType::RstStream
+                | FrameType::Continuation
+        );
+
+        if (is_connection_frame && stream != 0) || (is_stream_frame && stream == 0)
+        {
+            return Err(FrameError::Connection(ErrorCode::ProtocolError));
+        }
+
+        match kind
+        {
+            FrameType::Data => self.data_frame(flags, stream, payload, requests),
+            FrameType::Headers =>
+            {
+                let mut block = strip_padding(flags, payload)?;
+
+                if flags & FLAG_PRIORITY != 0
+                {
+                    block = block.get(5..)
+                        .ok_or(FrameError::Connection(ErrorCode::FrameSizeError))?;
+                }
+
+                let end_stream = flags & FLAG_END_STREAM != 0;
+
+                if flags & FLAG_END_HEADERS != 0
+                {
+                    self.header_block(stream, end_stream, block, requests)
+                } else
+                {
+                    let block = block.to_vec();
+                    self.continuation = Some(PartialBlock{stream, end_stream, block});
+
+                    Ok(())
+                }
+            },
+            FrameType::Continuation =>
+            {
+                let mut partial = self.continuation.take()
+                    .filter(|partial| partial.stream == stream)
+                    .ok_or(FrameError::Connection(ErrorCode::ProtocolError))?;
+
+                partial.block.extend(payload);
+
+                if partial.block.len() > MAX_HEADER_BLOCK
+                {
+                    return Err(FrameError::Connection(ErrorCode::EnhanceYourCalm));
+                }
+
+                if flags & FLAG_END_HEADERS != 0
+                {
+                    self.header_block(stream, partial.end_stream, &partial.block, requests)
+                } else
+                {
+                    self.continuation = Some(partial);
+
+                    Ok(())
+                }
+            },
+            FrameType::Priority =>
+            {
+                // priorities are just hints, everything gets sent round robin anyway
+                if payload.len() != 5
+                {
+                    return Err(FrameError::Stream(stream, ErrorCode::FrameSizeError));
+                }
+
+                Ok(())
+            },
+            FrameType::RstStream =>
+            {
+                if payload.len() != 4
+                {
+                    return Err(FrameError::Connection(ErrorCode::FrameSizeError));
+                }
+
+                if stream > self.last_stream
+                {
+                    return Err(FrameError::Connection(ErrorCode::ProtocolError));
+                }
+
+                self.streams.remove(&stream);
+
+                Ok(())
+            },
+            FrameType::Settings => self.settings_frame(flags, payload, writer),
+            FrameType::PushPromise => Err(FrameError::Connection(ErrorCode::ProtocolError)),
+            FrameType::Ping =>
+            {
+                if payload.len() != 8
+                {
+                    return Err(FrameError::Connection(ErrorCode::FrameSizeError));
+                }
+
+                if flags & FLAG_ACK == 0
+                {
+                    write_frame(writer, FrameType::Ping, FLAG_ACK, 0, payload)?;
+                }
+
+                Ok(())
+            },
+            FrameType::GoAway =>
+            {
+                if payload.len() < 8
+                {
+                    return Err(FrameError::Connection(ErrorCode::FrameSizeError));
+                }
+
+                self.peer_going_away = true;
+
+                Ok(())
+            },
+            FrameType::WindowUpdate => self.window_update_frame(stream, payload),
+            // unknown frames have to be ignored
+            FrameType::Unknown(_) => Ok(())
+        }
+    }
+
+    fn data_frame(
+        &mut self,
+        flags: u8,
+        id: u32,
+        payload: &[u8],
+        requests: &mut Vec<StreamRequest>
+    ) -> Result<(), FrameError>
+    {
+        let data = strip_padding(flags, payload)?;
+        let end_stream = flags & FLAG_END_STREAM != 0;
+
+        // padding counts too, it gets handed back with everything else that isnt buffered
+        self.receive_window -= payload.len() as i64;
+        if self.receive_window < 0
+        {
+            return Err(FrameError::Connection(ErrorCode::FlowControlError));
+        }
+
+        let Some(stream) = self.streams.get_mut(&id) else
+        {
+            // data for a stream thats already gone is fine, it was probably in flight
+            return if id > self.last_stream
+            {
+                Err(FrameError::Connection(ErrorCode::ProtocolError))
+            } else
+            {
+                Ok(())
+            };
+        };
+
+        if stream.remote_closed
+        {
+            return Err(FrameError::Stream(id, ErrorCode::StreamClosed));
+        }
+
+        stream.receive_window -= payload.len() as i64;
+        if stream.receive_window < 0
+        {
+            return Err(FrameError::Stream(id, ErrorCode::FlowControlError));
+        }
+
+        if !stream.discarding
+        {
+            stream.body.extend(data);
+
+            if stream.body.len() > MAX_BODY_LENGTH
+            {
+                let error = http::RequestError::BodyTooLarge(stream.body.len());
+                requests.push((id, Err(error.into())));
+
+                stream.body = Vec::new();
+                stream.discarding = true;
+            }
+        }
+
+        if end_stream
+        {
+            stream.remote_closed = true;
+
+            if !stream.discarding
+            {
+                requests.push((id, self.request(id)));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn header_block(
+        &mut self,
+        id: u32,
+        end_stream: bool,
+        block: &[u8],
+        requests: &mut Vec<StreamRequest>
+    ) -> Result<(), FrameError>
+    {
+        // has to be decoded even if the stream gets refused so the table stays in sync
+        let fields = self.decoder.decode(block)
+            .map_err(|_| FrameError::Connection(ErrorCode::CompressionError))?;
+
+        let fields = fields.into_iter().map(|(name, value)|
+        {
+            let name = String::from_utf8_lossy(&name).into_owned();
+
+            (name, String::from_utf8_lossy(&value).into_owned())
+        }).collect::<Vec<_>>();
+
+        if let Some(stream) = self.streams.get_mut(&id)
+        {
+            if stream.remote_closed
+            {
+                return Err(FrameError::Stream(id, ErrorCode::StreamClosed));
+            }
+
+            // headers after the first ones are trailers and have to end the stream
+            if !end_stream || fields.iter().any(|(name, _)| name.starts_with(':'))
+            {
+                return Err(FrameError::Stream(id, ErrorCode::ProtocolError));
+            }
+
+            stream.trailers = fields;
+            stream.remote_closed = true;
+
+            if !stream.discarding
+            {
+                requests.push((id, self.request(id)));
+            }
+
+            return Ok(());
+        }
+
+        // client streams are odd and always go up
+        if id.is_multiple_of(2) || id <= self.last_stream
+        {
+            return Err(FrameError::Connection(ErrorCode::ProtocolError));
+        }
+
+        self.last_stream = id;
+
+        if self.streams.len() >= MAX_CONCURRENT_STREAMS as usize
+        {
+            return Err(FrameError::Stream(id, ErrorCode::RefusedStream));
+        }
+
+        if !is_valid_request(&fields)
+        {
+            return Err(FrameError::Stream(id, ErrorCode::ProtocolError));
+        }
+
+        let mut stream = Stream::new(fields, self.initial_window);
+        stream.remote_closed = end_stream;
+
+        self.streams.insert(id, stream);
+
+        if end_stream
+        {
+            requests.push((id, self.request(id)));
+        }
+
+        Ok(())
+    }
+
+    fn settings_frame(
+        &mut self,
+        flags: u8,
+        payload: &[u8],
+        writer: &mut impl Write
+    ) -> Result<(), FrameError>
+    {
+        if flags & FLAG_ACK != 0
+        {
+            return if payload.is_empty()
+            {
+                Ok(())
+            } else
+            {
+                Err(FrameError::Connection(ErrorCode::FrameSizeError))
+            };
+        }
+
+        if !payload.len().is_multiple_of(6)
+        {
+            return Err(FrameError::Connection(ErrorCode::FrameSizeError));
+        }
+
+        payload.chunks_exact(6).try_for_each(|setting|
+        {
+            let id = u16::from_be_bytes([setting[0], setting[1]]);
+            let value = u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]]);
+
+            match id
+            {
+                // response headers never go into the dynamic table so its size doesnt matter
+                SETTINGS_HEADER_TABLE_SIZE => Ok(()),
+                SETTINGS_ENABLE_PUSH if value > 1 =>
+                {
+                    Err(FrameError::Connection(ErrorCode::ProtocolError))
+                },
+                SETTINGS_INITIAL_WINDOW_SIZE =>
+                {
+                    let value = value as i64;
+                    if value > MAX_WINDOW
+                    {
+                        return Err(FrameError::Connection(ErrorCode::FlowControlError));
+                    }
+
+                    let change = value - self.initial_window;
+                    self.initial_window = value;
+
+                    self.streams.values_mut().try_for_each(|stream|
+                    {
+                        stream.send_window += change;
+
+                        if stream.send_window > MAX_WINDOW
+                        {
+                            Err(FrameError::Connection(ErrorCode::FlowControlError))
+                        } else
+                        {
+                            Ok(())
+                        }
+                    })
+                },
+                SETTINGS_MAX_FRAME_SIZE =>
+                {
+                    let value = value as usize;
+                    if !(DEFAULT_FRAME_SIZE..=MAX_FRAME_SIZE).contains(&value)
+                    {
+                        return Err(FrameError::Connection(ErrorCode::ProtocolError));
+                    }
+
+                    self.max_frame_size = value;
+
+                    Ok(())
+                },
+                // anything else doesnt change how the server behaves
+                _ => Ok(())
+            }
+        })?;
+
+        write_frame(writer, FrameType::Settings, FLAG_ACK, 0, &[])?;
+
+        Ok(())
+    }
+
+    fn window_update_frame(&mut self, id: u32, payload: &[u8]) -> Result<(), FrameError>
+    {
+        let payload: [u8; 4] = payload.try_into()
+            .map_err(|_| FrameError::Connection(ErrorCode::FrameSizeError))?;
+
+        let increment = (u32::from_be_bytes(payload) & 0x7fff_ffff) as i64;
+
+        if id == 0
+        {
+            if increment == 0
+            {
+                return Err(FrameError::Connection(ErrorCode::ProtocolError));
+            }
+
+            self.send_window += increment;
+
+            if self.send_window > MAX_WINDOW
+            {
+                return Err(FrameError::Connection(ErrorCode::FlowControlError));
+            }
+
+            return Ok(());
+        }
+
+        let Some(stream) = self.streams.get_mut(&id) else
+        {
+            return if id > self.last_stream
+            {
+                Err(FrameError::Connection(ErrorCode::ProtocolError))
+            } else
+            {
+                Ok(())
+            };
+        };
+
+        if increment == 0
+        {
+            return Err(FrameError::Stream(id, ErrorCode::ProtocolError));
+        }
+
+        stream.send_window += increment;
+
+        if stream.send_window > MAX_WINDOW
+        {
+            return Err(FrameError::Stream(id, ErrorCode::FlowControlError));
+        }
+
+        Ok(())
+    }
+
+    // credit goes right back out for whatever arrived, bodies only get handed off once they
+    // end so holding it back until then would stall every upload bigger than the window,
+    // the connection window shrinks instead once the streams together near MAX_BUFFERED
+    fn grant_receive_window(&mut self, writer: &mut impl Write) -> io::Result<()>
+    {
+        let buffered = self.streams.values().map(|stream| stream.body.len()).sum::<usize>();
+
+        let allowed = MAX_BUFFERED.saturating_sub(buffered).min(DEFAULT_WINDOW as usize) as i64;
+        if allowed > self.receive_window
+        {
+            write_window_update(writer, 0, (allowed - self.receive_window) as usize)?;
+            self.receive_window = allowed;
+        }
+
+        // the connection window is what limits memory so streams can always get theirs back
+        let mut open = self.streams.iter_mut().filter(|(_, stream)| !stream.remote_closed);
+
+        open.try_for_each(|(id, stream)|
+        {
+            let increment = DEFAULT_WINDOW - stream.receive_window;
+            if increment > 0
+            {
+                write_window_update(writer, *id, increment as usize)?;
+                stream.receive_window = DEFAULT_WINDOW;
+            }
+
+            Ok(())
+        })
+    }
+
+    // turns a stream that the client finished sending into a request
+    fn request(&mut self, id: u32) -> Result<Request, http::Error>
+    {
+        let stream = self.streams.get_mut(&id).expect("only called for existing streams");
+
+        let fields = std::mem::take(&mut stream.fields);
+        let body = std::mem::take(&mut stream.body);
+
+        let pseudo = |name: &str|
+        {
+            fields.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+        };
+
+        let method = pseudo(":method").unwrap_or_default();
+        let path = pseudo(":path").unwrap_or_default();
+
+        let mut regular = fields.iter().filter(|(name, _)| !name.starts_with(':'))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        // the authority is what the host header used to be
+        if let Some(authority) = pseudo(":authority")
+        {
+            if !regular.iter().any(|(name, _)| name == "host")
+            {
+                regular.insert(0, ("host".to_owned(), authority.to_owned()));
+            }
+        }
+
+        Ok(Request::from_parts(method, path, &regular, body, &stream.trailers)?)
+    }
+
+    // the server is done with the stream, if the client isnt it gets told to stop
+    fn finish(&mut self, id: u32, writer: &mut impl Write) -> io::Result<()>
+    {
+        let remote_closed = self.streams.remove(&id).is_none_or(|stream| stream.remote_closed);
+
+        if !remote_closed
+        {
+            let code = ErrorCode::NoError.code().to_be_bytes();
+            write_frame(writer, FrameType::RstStream, 0, id, &code)?;
+        }
+
+        Ok(())
+    }
+
+    fn reset(&mut self, id: u32, code: ErrorCode, writer: &mut impl Write) -> io::Result<()>
+    {
+        self.streams.remove(&id);
+
+        write_frame(writer, FrameType::RstStream, 0, id, &code.code().to_be_bytes())
+    }
+
+    // the servers half of the connection preface, has to be the first thing it sends
+    fn send_settings(&mut self, writer: &mut impl Write) -> io::Result<()>
+    {
+        if self.settings_sent
+        {
+            return Ok(());
+        }
+
+        let settings = [
+            (SETTINGS_HEADER_TABLE_SIZE, hpack::MAX_TABLE_SIZE as u32),
+            (SETTINGS_MAX_CONCURRENT_STREAMS, MAX_CONCURRENT_STREAMS),
+            (SETTINGS_INITIAL_WINDOW_SIZE, DEFAULT_WINDOW as u32),
+            (SETTINGS_MAX_FRAME_SIZE, DEFAULT_FRAME_SIZE as u32)
+        ].into_iter().flat_map(|(id, value)|
+        {
+            id.to_be_bytes().into_iter().chain(value.to_be_bytes())
+        }).collect::<Vec<_>>();
+
+        self.settings_sent = true;
+
+        write_frame(writer, FrameType::Settings, 0, 0, &settings)
+    }
+
+    fn go_away(&mut self, code: ErrorCode, mut writer: impl Write) -> io::Result<()>
+    {
+        if self.closed
+        {
+            return Ok(());
+        }
+
+        self.send_settings(&mut writer)?;
+
+        self.closed = true;
+
+        let mut payload = self.last_stream.to_be_bytes().to_vec();
+        payload.extend(code.code().to_be_bytes());
+
+        write_frame(&mut writer, FrameType::GoAway, 0, 0, &payload)?;
+
+        writer.flush()
+    }
+
+    fn write_headers(
+        &mut self,
+        stream: u32,
+        block: &[u8],
+        end_stream: bool,
+        writer: &mut impl Write
+    ) -> io::Result<()>
+    {
+        let pieces = block.chunks(self.max_frame_size).collect::<Vec<_>>();
+        let last = pieces.len().saturating_sub(1);
+
+        // the first piece goes in a headers frame and the rest in continuations
+        pieces.into_iter().enumerate().try_for_each(|(index, piece)|
+        {
+            let (kind, mut flags) = if index == 0
+            {
+                (FrameType::Headers, if end_stream { FLAG_END_STREAM } else { 0 })
+            } else
+            {
+                (FrameType::Continuation, 0)
+            };
+
+            if index == last
+            {
+                flags |= FLAG_END_HEADERS;
+            }
+
+            write_frame(&mut *writer, kind, flags, stream, piece)
+        })
+    }
+}
+
+fn write_frame(
+    mut writer: impl Write,
+    kind: FrameType,
+    flags: u8,
+    stream: u32,
+    payload: &[u8]
+) -> io::Result<()>
+{
+    let mut frame = Vec::with_capacity(FRAME_HEADER_LENGTH + payload.len());
+
+    frame.extend(&(payload.len() as u32).to_be_bytes()[1..]);
+    frame.push(kind.as_byte());
+    frame.push(flags);
+    frame.extend((stream & 0x7fff_ffff).to_be_bytes());
+    frame.extend(payload);
+
+    writer.write_all(&frame)
+}
+
+fn write_window_update(writer: &mut impl Write, stream: u32, increment: usize) -> io::Result<()>
+{
+    write_frame(writer, FrameType::WindowUpdate, 0, stream, &(increment as u32).to_be_bytes())
+}
+
+fn strip_padding(flags: u8, payload: &[u8]) -> Result<&[u8], FrameError>
+{
+    if flags & FLAG_PADDED == 0
+    {
+        return Ok(payload);
+    }
+
+    let (&padding, rest) = payload.split_first()
+        .ok_or(FrameError::Connection(ErrorCode::FrameSizeError))?;
+
+    rest.len().checked_sub(padding as usize).map(|length| &rest[..length])
+        .ok_or(FrameError::Connection(ErrorCode::ProtocolError))
+}
+
+// checks the rules that make an http2 request malformed
+fn is_valid_request(fields: &[(String, String)]) -> bool
+{
+    let mut seen_regular = false;
+
+    let fields_valid = fields.iter().all(|(name, value)|
+    {
+        let is_pseudo = name.starts_with(':');
+
+        // pseudo headers only at the start
+        if is_pseudo && seen_regular
+        {
+            return false;
+        }
+
+        seen_regular |= !is_pseudo;
+
+        let known_pseudo = [":method", ":scheme", ":path", ":authority"].contains(&name.as_str());
+
+        let te_valid = name != "te" || value.eq_ignore_ascii_case("trailers");
+
+        !name.is_empty()
+            && !name.bytes().any(|c| c.is_ascii_uppercase())
+            && (!is_pseudo || known_pseudo)
+            && !CONNECTION_HEADERS.contains(&name.as_str())
+            && te_valid
+    });
+
+    let count = |name: &str| fields.iter().filter(|(key, _)| key == name).count();
+
+    fields_valid && count(":method") == 1 && count(":scheme") == 1 && count(":path") == 1
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+
+    fn frame(kind: FrameType, flags: u8, stream: u32, payload: &[u8]) -> Vec<u8>
+    {
+        let mut frame = Vec::new();
+        write_frame(&mut frame, kind, flags, stream, payload).unwrap();
+
+        frame
+    }
+
+    fn frames(mut output: &[u8]) -> Vec<(FrameType, u32, Vec<u8>)>
+    {
+        let mut frames = Vec::new();
+        while output.len() >= FRAME_HEADER_LENGTH
+        {
+            let length = u32::from_be_bytes([0, output[0], output[1], output[2]]) as usize;
+            let stream = u32::from_be_bytes([output[5], output[6], output[7], output[8]]);
+
+            let payload = output[FRAME_HEADER_LENGTH..FRAME_HEADER_LENGTH + length].to_vec();
+            frames.push((FrameType::parse(output[3]), stream, payload));
+
+            output = &output[FRAME_HEADER_LENGTH + length..];
+        }
+
+        frames
+    }
+
+    // a post thats still waiting for its body
+    fn upload_headers(id: u32) -> Vec<u8>
+    {
+        let fields = [(":method", "POST"), (":scheme", "https"), (":path", "/")]
+            .into_iter()
+            .map(|(name, value)| (name.to_owned(), value.to_owned()))
+            .collect::<Vec<_>>();
+
+        frame(FrameType::Headers, FLAG_END_HEADERS, id, &hpack::encode(&fields))
+    }
+
+    fn open_upload(connection: &mut Connection) -> Vec<u8>
+    {
+        let mut input = PREFACE.to_vec();
+        input.extend(frame(FrameType::Settings, 0, 0, &[]));
+        input.extend(upload_headers(1));
+
+        let mut output = Vec::new();
+        connection.receive(&input, &mut output).unwrap();
+
+        output
+    }
+
+    #[test]
+    fn survives_bad_table_size_update()
+    {
+        let mut connection = Connection::new(false);
+        open_upload(&mut connection);
+
+        let mut output = Vec::new();
+        let input = frame(FrameType::Headers, FLAG_END_HEADERS | FLAG_END_STREAM, 3, &[0x3f]);
+        connection.receive(&input, &mut output).unwrap();
+
+        let (kind, _, payload) = frames(&output).pop().unwrap();
+        assert_eq!(kind, FrameType::GoAway);
+        assert_eq!(payload[4..], ErrorCode::CompressionError.code().to_be_bytes());
+    }
+
+    #[test]
+    fn regrants_window_below_the_buffer_cap()
+    {
+        let mut connection = Connection::new(false);
+        open_upload(&mut connection);
+
+        let chunk = vec![b'a'; DEFAULT_FRAME_SIZE];
+
+        let updates = |output: &[u8]|
+        {
+            frames(output).into_iter().filter(|(kind, _, _)|
+            {
+                *kind == FrameType::WindowUpdate
+            }).map(|(_, stream, payload)|
+            {
+                (stream, u32::from_be_bytes(payload.try_into().unwrap()))
+            }).collect::<Vec<_>>()
+        };
+
+        // nothing got consumed yet but the credit comes back anyway while theres room
+        let mut output = Vec::new();
+        connection.receive(&frame(FrameType::Data, 0, 1, &chunk), &mut output).unwrap();
+
+        let full = DEFAULT_FRAME_SIZE as u32;
+        assert_eq!(updates(&output), [(0, full), (1, full)]);
+
+        // with a whole body already waiting the connection keeps what it has left
+        connection.streams.get_mut(&1).unwrap().body = vec![0; MAX_BODY_LENGTH];
+
+        let mut input = upload_headers(3);
+        input.extend(frame(FrameType::Data, 0, 3, &chunk));
+
+        let mut output = Vec::new();
+        connection.receive(&input, &mut output).unwrap();
+
+        assert_eq!(updates(&output), [(3, full)]);
+        assert_eq!(connection.receive_window, DEFAULT_WINDOW - DEFAULT_FRAME_SIZE as i64);
+    }
+
+    #[test]
+    fn rejects_data_past_the_window()
+    {
+        let mut connection = Connection::new(false);
+        open_upload(&mut connection);
+
+        // all of it arrives before the server got a chance to hand out more credit
+        let mut input = Vec::new();
+        (0..4).for_each(|_| input.extend(frame(FrameType::Data, 0, 1, &[0; DEFAULT_FRAME_SIZE])));
+        input.extend(frame(FrameType::Data, 0, 1, &[0; 4]));
+
+        let mut output = Vec::new();
+        connection.receive(&input, &mut output).unwrap();
+
+        let (kind, _, payload) = frames(&output).pop().unwrap();
+        assert_eq!(kind, FrameType::GoAway);
+        assert_eq!(payload[4..], ErrorCode::FlowControlError.code().to_be_bytes());
+    }
+}
@@ -1,5 +1,4 @@
 use std::{
-    fs,
     sync::Arc,
     net::TcpStream,
     io::{Write, Read}
@@ -7,62 +6,166 @@ use std::{
 
 use rustls::{pki_types::ServerName, ClientConnection, ClientConfig, RootCertStore};
 
-use super::{http::{self, RequestField}, SmolServer, Error, Status, ContentType, Request};
+use serde::Deserialize;
+
+use super::{
+    http::{self, Headers, RequestError},
+    SmolServer,
+    Settings,
+    Error,
+    Status,
+    ContentType,
+    Request,
+    Response
+};
 
 
-fn encode_data<'a>(
-    mut fields: impl Iterator<Item=&'a RequestField>,
-    data: &[u8]
-) -> Result<Vec<u8>, Error>
+// an https url that posts get forwarded to
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct ForwardTarget
 {
-    let mut content = Vec::new();
+    pub host: String,
+    pub port: u16,
+    pub path: String
+}
 
-    let content_disposition = fields.find(|field|
-    {
-        field.this.name == "Content-Disposition"
-    }).map(|field| field.children.clone()).expect("must have a content disposition");
+impl TryFrom<String> for ForwardTarget
+{
+    type Error = String;
 
-    let name: String = content_disposition.iter().find(|field|
+    fn try_from(url: String) -> Result<Self, Self::Error>
     {
-        field.name == "name"
-    }).map(|field| field.body.clone()).expect("must have a name");
+        let rest = url.strip_prefix("https://")
+            .ok_or_else(|| format!("{url} has to start with https://"))?;
 
-    let (content_type, content_disposition) = if name == "text_message"
-    {
-        (None, format!("name=\"content\""))
-    } else
-    {
-        let filename = content_disposition.iter().find(|field|
+        let (authority, path) = rest.find('/').map_or((rest, "/"), |index|
         {
-            field.name == "filename"
-        }).map(|field| field.body.clone()).expect("must have a filename");
+            (&rest[..index], &rest[index..])
+        });
 
-        let content_type = SmolServer::extension_content_type(&filename)?;
+        let (host, port) = match authority.rsplit_once(':')
+        {
+            Some((host, port)) =>
+            {
+                let port = port.parse().map_err(|_| format!("{url} has an invalid port"))?;
 
-        (Some(content_type), format!("name=\"files[0]\"; filename=\"{filename}\""))
-    };
+                (host, port)
+            },
+            None => (authority, 443)
+        };
+
+        if host.is_empty()
+        {
+            return Err(format!("{url} doesnt have a host"));
+        }
+
+        ServerName::try_from(host).map_err(|_| format!("{host} isnt a valid server name"))?;
+
+        Ok(Self{host: host.to_owned(), port, path: path.to_owned()})
+    }
+}
+
+fn encode_part(
+    content_disposition: &str,
+    content_type: Option<ContentType>,
+    data: &[u8]
+) -> Vec<u8>
+{
+    let mut content = Vec::new();
 
     content.extend(format!("Content-Disposition: form-data; {content_disposition}\r\n")
         .as_bytes());
 
     if let Some(content_type) = content_type
     {
-        content.extend(content_type.as_bytes());
-        content.extend(b"\r\n");
+        content.extend(format!("Content-Type: {}\r\n", content_type.as_str()).as_bytes());
     }
     
     content.extend(b"\r\n");
     content.extend(data);
     content.extend(b"\r\n");
 
-    Ok(content)
+    content
+}
+
+fn encode_text(text: &[u8]) -> Vec<u8>
+{
+    encode_part("name=\"content\"", None, text)
+}
+
+fn encode_data(headers: &Headers, data: &[u8]) -> Result<Vec<u8>, Error>
+{
+    let malformed = |reason: &str|
+    {
+        Error::from(http::Error::from(RequestError::MalformedPart(reason.to_owned())))
+    };
+
+    let content_disposition = headers.get("Content-Disposition")
+        .ok_or_else(|| malformed("no content disposition"))?;
+
+    let name = content_disposition.child("name").ok_or_else(|| malformed("no name"))?;
+
+    if name == "text_message"
+    {
+        Ok(encode_text(data))
+    } else
+    {
+        let filename = content_disposition.child("filename")
+            .ok_or_else(|| malformed("no filename"))?;
+
+        let content_type = SmolServer::extension_content_type(filename)?;
+
+        let content_disposition = format!("name=\"files[0]\"; filename=\"{filename}\"");
+
+        Ok(encode_part(&content_disposition, Some(content_type), data))
+    }
 }
 
 // this function does nothing on the public version that i upload
 // but im doing my own stuff in here!
-pub fn handle(mut writer: impl Write, request: Request) -> Result<(), Error>
+// none means it went through and the page itself should get sent back
+pub fn handle(settings: &Settings, request: &Request) -> Result<Option<Response>, Error>
 {
-    let mut stream = TcpStream::connect("discord.com:443")?;
+    let essence = request.headers.content_type().map(|content_type| content_type.essence);
+
+    let parts_content = match essence.as_deref()
+    {
+        Some("multipart/form-data") =>
+        {
+            request.data.iter().filter(|data|
+            {
+                !data.data.is_empty()
+            }).map(|data|
+            {
+                encode_data(&data.headers, &data.data)
+            }).collect::<Result<Vec<Vec<u8>>, _>>()?
+        },
+        Some("application/x-www-form-urlencoded") =>
+        {
+            request.form().unwrap_or_default().into_iter().filter(|(name, value)|
+            {
+                name == "text_message" && !value.is_empty()
+            }).map(|(_, value)|
+            {
+                encode_text(value.as_bytes())
+            }).collect()
+        },
+        Some("text/plain") => vec![encode_text(&request.body)],
+        _ => return Ok(Some(Response::new(Status::UnsupportedMediaType)))
+    };
+
+    settings.post_forward.iter().try_for_each(|target|
+    {
+        forward(target, &parts_content)
+    })?;
+
+    Ok(None)
+}
+
+fn forward(target: &ForwardTarget, parts_content: &[Vec<u8>]) -> Result<(), Error>
+{
+    let mut stream = TcpStream::connect((target.host.as_str(), target.port))?;
 
     let mut root_certs = RootCertStore::empty();
 
@@ -76,16 +179,17 @@ pub fn handle(mut writer: impl Write, request: Request) -> Result<(), Error>
         .with_root_certificates(root_certs)
         .with_no_client_auth());
 
-    let name = ServerName::try_from("discord.com").unwrap();
+    let name = ServerName::try_from(target.host.clone())
+        .expect("forward targets get checked when the config loads");
+
     let mut client_tls = ClientConnection::new(config, name)?;
 
-    let mut discord_sender = rustls::Stream::new(&mut client_tls, &mut stream);
+    let mut forward_sender = rustls::Stream::new(&mut client_tls, &mut stream);
 
     let boundary = "-----------------------------MYCOOLBOUNDARY8888";
     let boundary_combined = format!("--{boundary}");
 
-    let webhook_url = include_str!("../../test/webhook");
-    let mut send_data = format!("POST {webhook_url} HTTP/1.1\r\nHost: discord.com\r\n")
+    let mut send_data = format!("POST {} HTTP/1.1\r\nHost: {}\r\n", target.path, target.host)
         .into_bytes();
 
     send_data.extend(format!("Content-Type: multipart/form-data; boundary=\"{boundary}\"\r\n")
@@ -95,15 +199,7 @@ pub fn handle(mut writer: impl Write, request: Request) -> Result<(), Error>
 
     content.extend(format!("{boundary_combined}\r\n").as_bytes());
 
-    let parts_content = request.data.iter().filter(|data|
-    {
-        !data.data.is_empty()
-    }).map(|data|
-    {
-        encode_data(data.fields.iter(), &data.data)
-    }).collect::<Result<Vec<Vec<u8>>, _>>()?;
-
-    let add_content = parts_content.into_iter().fold(Vec::new(), |mut acc, part|
+    let add_content = parts_content.iter().fold(Vec::new(), |mut acc, part|
     {
         acc.extend(format!("{boundary_combined}\r\n").as_bytes());
         acc.extend(part);
@@ -121,21 +217,14 @@ pub fn handle(mut writer: impl Write, request: Request) -> Result<(), Error>
 
     send_data.extend(&content);
 
-    discord_sender.write_all(&send_data)?;
+    forward_sender.write_all(&send_data)?;
 
     let mut buffer = vec![0; 6400];
-    let amount = discord_sender.read(&mut buffer).unwrap();
+    let amount = forward_sender.read(&mut buffer)?;
 
     let _response = &buffer[0..amount];
 
     // println!("{}", String::from_utf8_lossy(_response));
 
-    let path = SmolServer::relative_path(&request.header.body)?;
-    let data = fs::read(path)?;
-
-    let response = http::response(Status::Ok, ContentType::Html, &data);
-
-    writer.write_all(&response)?;
-
     Ok(())
 }
@@ -0,0 +1,51 @@
+use std::{
+    fs,
+    env,
+    process,
+    ops::Deref,
+    path::{Path, PathBuf}
+};
+
+
+// a scratch directory under the temp dir that gets removed again even when a test panics
+pub struct TestDir(PathBuf);
+
+impl TestDir
+{
+    pub fn new(name: &str) -> Self
+    {
+        // tests run in parallel so every name has to be unique across the whole crate
+        let path = env::temp_dir().join(format!("funserver_{name}_{}", process::id()));
+
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+
+        Self(path)
+    }
+}
+
+impl Deref for TestDir
+{
+    type Target = Path;
+
+    fn deref(&self) -> &Self::Target
+    {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TestDir
+{
+    fn as_ref(&self) -> &Path
+    {
+        &self.0
+    }
+}
+
+impl Drop for TestDir
+{
+    fn drop(&mut self)
+    {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}